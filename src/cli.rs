@@ -1,8 +1,22 @@
-use std::{error::Error, path::Path};
+use std::{
+    error::Error,
+    io::{self, Write},
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 
-use crate::{db::Database, gui, post::Post, search};
+use crate::{
+    db::{Database, Page},
+    gui,
+    import::{self, FolderTags},
+    maintenance::{self, Problem},
+    metatag::{self, Order},
+    post,
+    search::{self, Query},
+    sidecar, tag,
+};
 
 #[derive(Parser, Debug)]
 #[clap(trailing_var_arg = true)]
@@ -21,34 +35,161 @@ enum Mode {
         #[command(subcommand)]
         mode: RemoveType,
     },
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
     Tag {
+        #[command(subcommand)]
+        action: Option<TagAction>,
+
         #[arg(long, short)]
         remove: bool,
 
         #[arg(required = true)]
-        post_id: i64,
+        post_id: Option<i64>,
 
         #[arg(required = true)]
         tags: Vec<String>,
     },
 
     Search {
-        #[arg(required = true, allow_hyphen_values = true)]
+        #[arg(long, value_parser = Order::parse)]
+        order: Option<Order>,
+
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+
+        #[arg(long)]
+        limit: Option<usize>,
+
+        #[arg(allow_hyphen_values = true)]
         tags: Vec<String>,
     },
+    Tags {
+        #[arg(long, value_enum, default_value_t = TagSort::Count)]
+        sort: TagSort,
+
+        #[arg(long)]
+        limit: Option<usize>,
+
+        #[arg(long)]
+        json: bool,
+
+        prefix: Option<String>,
+    },
+    Gc {
+        #[arg(long, short = 'n')]
+        dry_run: bool,
+    },
+    Thumbnails {
+        #[command(subcommand)]
+        action: ThumbnailAction,
+    },
+    Dupes {
+        #[arg(long, short, default_value_t = maintenance::DUPLICATE_THRESHOLD)]
+        threshold: u32,
+
+        #[arg(long, short, default_value_t = 1)]
+        jobs: usize,
+    },
+    Metadata {
+        #[arg(long, short, default_value_t = 1)]
+        jobs: usize,
+    },
+    Merge {
+        keep: i64,
+        drop: i64,
+    },
+    Verify {
+        #[arg(long, short, default_value_t = 1)]
+        jobs: usize,
+
+        #[arg(allow_hyphen_values = true)]
+        query: Vec<String>,
+    },
+    Alias {
+        #[command(subcommand)]
+        action: AliasAction,
+    },
+    Implication {
+        #[command(subcommand)]
+        action: ImplicationAction,
+    },
     Gui,
 }
 
+#[derive(Subcommand, Debug)]
+enum ThumbnailAction {
+    Regen {
+        #[arg(long)]
+        force: bool,
+
+        #[arg(long, short, default_value_t = 1)]
+        jobs: usize,
+    },
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum TagSort {
+    Count,
+    Name,
+}
+
+#[derive(Subcommand, Debug)]
+enum TagAction {
+    Rename { tag: String, new_name: String },
+    Merge { tag: String, into: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum ImplicationAction {
+    Add { tag: String, implied_tag: String },
+    List,
+    Remove { tag: String, implied_tag: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum AliasAction {
+    Add {
+        alias: String,
+        tag: String,
+
+        #[arg(long)]
+        merge: bool,
+    },
+    List,
+    Remove {
+        #[arg(required = true)]
+        aliases: Vec<String>,
+    },
+}
+
 #[derive(Subcommand, Debug)]
 enum AddType {
     File {
+        #[arg(long = "tag", short)]
+        tags: Vec<String>,
+
+        #[arg(long)]
+        folder_tags: bool,
+
+        #[arg(long, requires = "folder_tags")]
+        folder_namespace: Option<String>,
+
+        #[arg(long, requires = "folder_tags")]
+        folder_depth: Option<usize>,
+
         #[arg(required = true)]
-        files: Vec<String>,
+        files: Vec<PathBuf>,
     },
     Tag {
         #[arg(required = true)]
         tags: Vec<String>,
     },
+    Namespace {
+        name: String,
+
+        #[arg(long, value_parser = tag::parse_color)]
+        color: Option<u32>,
+    },
 }
 
 #[derive(Subcommand, Debug)]
@@ -61,6 +202,10 @@ enum RemoveType {
         #[arg(required = true)]
         tags: Vec<String>,
     },
+    Namespace {
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
 }
 
 impl Cli {
@@ -69,11 +214,37 @@ impl Cli {
 
         match cli.command {
             Mode::Add { mode } => match mode {
-                AddType::File { files } => {
+                AddType::File {
+                    tags,
+                    folder_tags,
+                    folder_namespace,
+                    folder_depth,
+                    files,
+                } => {
+                    let options = import::Options {
+                        tags,
+                        folder_tags: folder_tags.then_some(FolderTags {
+                            namespace: folder_namespace,
+                            depth: folder_depth,
+                        }),
+                    };
+                    let files = sidecar::remove_sidecars(import::files(files), &db.config);
+
                     db.begin()?;
                     for file in files {
-                        let post = Post::new(Path::new(&file), &mut db)?;
-                        println!("{} -> Post #{}", file, post.id);
+                        let (post, tags) = import::post(&file, &options, &mut db)?;
+                        let path = file.path.display();
+
+                        match tags.is_empty() {
+                            true => println!("{} -> Post #{}", path, post.id),
+                            false => println!(
+                                "{} -> Post #{} with {} tag{}",
+                                path,
+                                post.id,
+                                tags.len(),
+                                plural(tags.len())
+                            ),
+                        }
                     }
                     db.commit()?;
                 }
@@ -85,6 +256,13 @@ impl Cli {
                     }
                     db.commit()?;
                 }
+                AddType::Namespace { name, color } => {
+                    if metatag::KEYS.contains(&name.as_str()) {
+                        return Err(format!("'{}' is reserved for searching", name).into());
+                    }
+                    db.insert_namespace(&name, color)?;
+                    println!("Added namespace '{}'", name);
+                }
             },
             Mode::Remove { mode } => match mode {
                 RemoveType::File { post_ids } => {
@@ -104,38 +282,360 @@ impl Cli {
                     }
                     db.commit()?;
                 }
+                RemoveType::Namespace { names } => {
+                    for name in names {
+                        match db.remove_namespace(&name)? {
+                            0 => println!("No namespace '{}'", name),
+                            _ => println!("Removing namespace '{}'", name),
+                        }
+                    }
+                }
             },
             Mode::Tag {
+                action: Some(action),
+                ..
+            } => {
+                let (tag, new_name, must_exist) = match action {
+                    TagAction::Rename { tag, new_name } => (tag, new_name, false),
+                    TagAction::Merge { tag, into } => (tag, into, true),
+                };
+
+                let tag_id = db
+                    .get_tag_id(&tag)
+                    .map_err(|_| format!("No tag '{}'", tag))?;
+                let new_name = db.canonical_tag(&new_name)?;
+                if must_exist {
+                    db.get_tag_id(&new_name)
+                        .map_err(|_| format!("No tag '{}'", new_name))?;
+                }
+
+                db.begin()?;
+                let changed = db.rename_tag(tag_id, &new_name)?;
+                db.commit()?;
+                println!(
+                    "'{}' -> '{}'. Changed {} post{}",
+                    tag,
+                    new_name,
+                    changed,
+                    plural(changed)
+                );
+            }
+
+            Mode::Tag {
+                action: None,
                 remove,
                 post_id,
                 tags,
             } => {
-                let mut post = db.get_post_id(post_id)?;
+                let mut post = db.get_post_id(post_id.unwrap())?;
                 let tag_count = post.tags.len();
 
                 db.begin()?;
-                let (_, action) = match remove {
-                    true => (post.remove_tags(&tags, &mut db), "Removed"),
-                    false => (post.add_tags(&tags, &mut db), "Added"),
+                let (warnings, action) = match remove {
+                    true => (post.remove_tags(&tags, &mut db)?, "Removed"),
+                    false => (post.add_tags(&tags, &mut db).map(|_| Vec::new())?, "Added"),
                 };
                 db.commit()?;
 
-                let diff = tag_count.abs_diff(post.tags.len());
-                let plural = match diff {
-                    1 => "",
-                    _ => "s",
-                };
+                for warning in warnings {
+                    eprintln!("Warning: {}", warning);
+                }
 
-                println!("{} {} tag{}. New {}", action, diff, plural, post);
+                let diff = tag_count.abs_diff(post.tags.len());
+                let namespaces = db.get_namespaces()?;
+                println!(
+                    "{} {} tag{}. New {}",
+                    action,
+                    diff,
+                    plural(diff),
+                    post.details(&namespaces)
+                );
             }
 
-            Mode::Search { tags } => {
-                let posts = search::new(tags, &mut db)?;
+            Mode::Search {
+                order,
+                offset,
+                limit,
+                tags,
+            } => {
+                let mut query = search::parse(&tags.join(" "))?;
+                if let Some(order) = order {
+                    query.order = order;
+                }
+
+                println!("Searching for: {}", query);
+                let posts = query.run(&Page { offset, limit }, &db)?;
+                let namespaces = db.get_namespaces()?;
                 for post in posts {
-                    println!("{}", post);
+                    println!("{}", post.details(&namespaces));
+                }
+            }
+
+            Mode::Tags {
+                sort,
+                limit,
+                json,
+                prefix,
+            } => {
+                let by_count = matches!(sort, TagSort::Count);
+                let page = Page { offset: 0, limit };
+                let tags = db.get_tag_counts(&prefix.unwrap_or_default(), by_count, &page)?;
+
+                match json {
+                    true => println!("{}", serde_json::to_string_pretty(&tags)?),
+                    false => {
+                        for tag in tags {
+                            println!("{:>8}  {}", tag.count, tag.name);
+                        }
+                    }
+                }
+            }
+
+            Mode::Gc { dry_run } => {
+                let tags = db.get_orphan_tags()?;
+                let files = maintenance::orphan_files(&db)?;
+                let bytes: u64 = files.iter().map(|(_, size)| size).sum();
+
+                for tag in &tags {
+                    println!("Orphan tag '{}'", tag);
+                }
+                for (path, size) in &files {
+                    println!(
+                        "Orphan file {} ({})",
+                        path.display(),
+                        post::format_bytes(*size)
+                    );
+                }
+
+                if dry_run {
+                    println!(
+                        "Would remove {} tag{} and {} file{}, freeing {}",
+                        tags.len(),
+                        plural(tags.len()),
+                        files.len(),
+                        plural(files.len()),
+                        post::format_bytes(bytes)
+                    );
+                    return Ok(());
+                }
+
+                db.begin()?;
+                for tag in &tags {
+                    db.remove_tag(tag)?;
+                }
+                db.commit()?;
+
+                for (path, _) in &files {
+                    maintenance::remove_stored_file(path)?;
+                }
+
+                println!(
+                    "Removed {} tag{} and {} file{}, freed {}",
+                    tags.len(),
+                    plural(tags.len()),
+                    files.len(),
+                    plural(files.len()),
+                    post::format_bytes(bytes)
+                );
+            }
+
+            Mode::Thumbnails {
+                action: ThumbnailAction::Regen { force, jobs },
+            } => {
+                let mut posts = Query::default().run(&Page::default(), &db)?;
+                if !force {
+                    posts.retain(|post| {
+                        let thumbnails = post.get_db_thumbnails(&db.config);
+                        !thumbnails.iter().all(|(_, path)| path.exists())
+                    });
+                }
+
+                println!(
+                    "Creating {} thumbnail{}...",
+                    posts.len(),
+                    plural(posts.len())
+                );
+                let failures = maintenance::regen_thumbnails(&posts, jobs, &db.config);
+
+                for (post_id, e) in &failures {
+                    println!("Failed: Post #{} ({})", post_id, e);
+                }
+                println!(
+                    "Created {} thumbnail{}, {} failed",
+                    posts.len() - failures.len(),
+                    plural(posts.len() - failures.len()),
+                    failures.len()
+                );
+            }
+
+            Mode::Dupes { threshold, jobs } => {
+                let hashed = maintenance::fill_phashes(&db, jobs)?;
+                if hashed > 0 {
+                    println!("Hashed {} image{}", hashed, plural(hashed));
+                }
+
+                let groups = maintenance::find_duplicates(&db, threshold)?;
+                for (i, group) in groups.iter().enumerate() {
+                    println!("Group {}:", i + 1);
+                    for post in group {
+                        println!(
+                            "  #{:<6} {:>11} {:>10}  {}",
+                            post.id,
+                            post.dimensions().unwrap_or_default(),
+                            post.file_size
+                                .map(|size| post::format_bytes(size as u64))
+                                .unwrap_or_default(),
+                            post.original_name
+                        );
+                    }
+                }
+                println!("Found {} group{}", groups.len(), plural(groups.len()));
+            }
+
+            Mode::Metadata { jobs } => {
+                let posts = db.get_posts_without_metadata()?;
+                let done = AtomicUsize::new(0);
+                maintenance::backfill_metadata(&posts, jobs, &db, |post| {
+                    let n = done.fetch_add(1, Ordering::Relaxed) + 1;
+                    eprintln!("[{}/{}] Post #{}", n, posts.len(), post.id);
+                })?;
+                println!(
+                    "Read metadata of {} post{}",
+                    posts.len(),
+                    plural(posts.len())
+                );
+            }
+
+            Mode::Merge { keep, drop } => {
+                let mut post = db.get_post_id(keep)?;
+                let other = db.get_post_id(drop)?;
+                post.merge(other, true, &db)?;
+                let namespaces = db.get_namespaces()?;
+                println!(
+                    "Merged post #{} into #{}. New {}",
+                    drop,
+                    keep,
+                    post.details(&namespaces)
+                );
+            }
+
+            Mode::Verify { jobs, query } => {
+                let all_posts = query.is_empty();
+                let query = search::parse(&query.join(" "))?;
+                let posts = query.run(&Page::default(), &db)?;
+
+                println!("Verifying {} post{}...", posts.len(), plural(posts.len()));
+                let problems = maintenance::verify(&posts, all_posts, jobs, &db.config)?;
+
+                for (path, problem) in &problems {
+                    match problem {
+                        Problem::Missing => println!("Missing: {}", path.display()),
+                        Problem::Corrupt(hash) => {
+                            println!("Corrupt: {} (hashes to {})", path.display(), hash)
+                        }
+                        Problem::Unreadable(e) => {
+                            println!("Unreadable: {} ({})", path.display(), e)
+                        }
+                        Problem::Unexpected => println!("Unexpected: {}", path.display()),
+                    }
+                }
+
+                if !problems.is_empty() {
+                    return Err(format!(
+                        "{} problem{} found",
+                        problems.len(),
+                        plural(problems.len())
+                    )
+                    .into());
                 }
+                println!("All files match");
             }
 
+            Mode::Alias { action } => match action {
+                AliasAction::Add { alias, tag, merge } => {
+                    let tag = db.canonical_tag(&tag)?;
+                    if alias == tag {
+                        return Err(format!("'{}' can't be an alias of itself", alias).into());
+                    }
+
+                    db.begin()?;
+                    let tag_id = db.get_or_create_tag(&tag)?;
+                    if let Ok(alias_id) = db.get_tag_id(&alias) {
+                        let count = db.count_tag_posts(alias_id)?;
+                        let question = format!(
+                            "{} post{} already tagged '{}'. Merge onto '{}'?",
+                            count,
+                            plural(count),
+                            alias,
+                            tag
+                        );
+                        if merge || confirm(&question)? {
+                            let merged = db.merge_tags(alias_id, tag_id)?;
+                            println!("Merged {} post{}", merged, plural(merged));
+                        }
+                    }
+                    db.insert_alias(&alias, tag_id)?;
+                    db.commit()?;
+                    println!("'{}' -> '{}'", alias, tag);
+                }
+                AliasAction::List => {
+                    for (alias, tag) in db.get_aliases()? {
+                        println!("{} -> {}", alias, tag);
+                    }
+                }
+                AliasAction::Remove { aliases } => {
+                    for alias in aliases {
+                        match db.remove_alias(&alias)? {
+                            0 => println!("No alias '{}'", alias),
+                            _ => println!("Removing alias '{}'", alias),
+                        }
+                    }
+                }
+            },
+
+            Mode::Implication { action } => match action {
+                ImplicationAction::Add { tag, implied_tag } => {
+                    let tag = db.canonical_tag(&tag)?;
+                    let implied_tag = db.canonical_tag(&implied_tag)?;
+
+                    db.begin()?;
+                    let tag_id = db.get_or_create_tag(&tag)?;
+                    let implied_id = db.get_or_create_tag(&implied_tag)?;
+                    if tag_id == implied_id || db.implies(implied_id, tag_id)? {
+                        db.rollback()?;
+                        return Err(format!(
+                            "'{}' already implies '{}', this would create a cycle",
+                            implied_tag, tag
+                        )
+                        .into());
+                    }
+
+                    db.insert_implication(tag_id, implied_id)?;
+                    let added = db.apply_implications(tag_id)?;
+                    db.commit()?;
+                    println!(
+                        "'{}' -> '{}'. Added {} tagging{} to existing posts",
+                        tag,
+                        implied_tag,
+                        added,
+                        plural(added)
+                    );
+                }
+                ImplicationAction::List => {
+                    for (tag, implied_tag) in db.get_implications()? {
+                        println!("{} -> {}", tag, implied_tag);
+                    }
+                }
+                ImplicationAction::Remove { tag, implied_tag } => {
+                    let tag_id = db.get_tag_id(&db.canonical_tag(&tag)?)?;
+                    let implied_id = db.get_tag_id(&db.canonical_tag(&implied_tag)?)?;
+                    match db.remove_implication(tag_id, implied_id)? {
+                        0 => println!("'{}' doesn't imply '{}'", tag, implied_tag),
+                        _ => println!("Removing '{}' -> '{}'", tag, implied_tag),
+                    }
+                }
+            },
+
             Mode::Gui => {
                 gui::run(db)?;
             }
@@ -144,3 +644,19 @@ impl Cli {
         Ok(())
     }
 }
+
+fn plural(count: usize) -> &'static str {
+    match count {
+        1 => "",
+        _ => "s",
+    }
+}
+
+fn confirm(question: &str) -> io::Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
@@ -1,5 +1,6 @@
+use crate::{sidecar, thumbnail};
 use serde_derive::{Deserialize, Serialize};
-use std::{fs, io, path::Path};
+use std::{collections::BTreeMap, fs, io, path::Path};
 
 const PATH: &str = "config.toml";
 
@@ -13,6 +14,33 @@ pub struct Config {
 
     #[serde(default = "db_thumbnail_path")]
     pub db_thumbnail_path: String,
+
+    #[serde(default = "thumbnail_size")]
+    pub thumbnail_size: u32,
+
+    /// More sizes stored for zooming the grid, besides `thumbnail_size`.
+    #[serde(default = "thumbnail_zoom_sizes")]
+    pub thumbnail_zoom_sizes: Vec<u32>,
+
+    #[serde(default)]
+    pub thumbnail_format: thumbnail::Format,
+
+    #[serde(default = "thumbnail_quality")]
+    pub thumbnail_quality: u8,
+
+    #[serde(default)]
+    pub thumbnail_filter: thumbnail::Filter,
+
+    #[serde(default)]
+    pub thumbnail_background: thumbnail::Background,
+
+    #[serde(default = "sidecar::Format::all")]
+    pub sidecar_formats: Vec<sidecar::Format>,
+
+    /// JSON sidecar fields to read tags from, and the namespace to put them
+    /// in. `""` for none.
+    #[serde(default = "sidecar_json_fields")]
+    pub sidecar_json_fields: BTreeMap<String, String>,
 }
 
 fn db_sql_path() -> String {
@@ -27,6 +55,22 @@ fn db_thumbnail_path() -> String {
     "./db/thumbnails".to_string()
 }
 
+fn thumbnail_size() -> u32 {
+    180
+}
+
+fn thumbnail_zoom_sizes() -> Vec<u32> {
+    vec![90, 360]
+}
+
+fn thumbnail_quality() -> u8 {
+    85
+}
+
+fn sidecar_json_fields() -> BTreeMap<String, String> {
+    BTreeMap::from([("tags".to_string(), String::new())])
+}
+
 impl Config {
     pub fn get() -> Self {
         let mut create_new = false;
@@ -53,6 +97,15 @@ impl Config {
         config
     }
 
+    /// Every size thumbnails are stored in, smallest first.
+    pub fn thumbnail_sizes(&self) -> Vec<u32> {
+        let mut sizes = self.thumbnail_zoom_sizes.clone();
+        sizes.push(self.thumbnail_size);
+        sizes.sort();
+        sizes.dedup();
+        sizes
+    }
+
     fn create_config_dirs(&self) -> io::Result<()> {
         fs::create_dir_all(Path::new(&self.db_sql_path).parent().unwrap())?;
         fs::create_dir_all(Path::new(&self.db_file_path))?;
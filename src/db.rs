@@ -1,7 +1,139 @@
-use std::{collections::HashSet, rc::Rc};
+use std::{
+    collections::HashSet,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use crate::{config::Config, post::Post};
-use rusqlite::{types::Value, Connection, Error, Result, Row};
+use crate::{config::Config, media::Metadata, post::Post, tag, tag::Namespace};
+use rusqlite::{
+    functions::FunctionFlags, params_from_iter, types::Value, Connection, Error, OptionalExtension,
+    Result, Row,
+};
+use serde_derive::Serialize;
+
+/// Schema migrations, applied in order. `PRAGMA user_version` stores how many
+/// have run, so new schema changes must be appended and never edited.
+const MIGRATIONS: &[&str] = &[
+    // v1: initial schema
+    "
+    CREATE TABLE IF NOT EXISTS posts (
+    post_id INTEGER PRIMARY KEY,
+    blake3 BLOB NOT NULL UNIQUE,
+    extension TEXT,
+    original_name TEXT);
+
+    CREATE TABLE IF NOT EXISTS tags (
+    tag_id INTEGER PRIMARY KEY,
+    tag_name TEXT NOT NULL UNIQUE);
+
+    CREATE TABLE IF NOT EXISTS taggings (
+    tagging_id INTEGER PRIMARY KEY,
+    post_id INTEGER NOT NULL,
+    tag_id INTEGER NOT NULL,
+    UNIQUE(post_id, tag_id) ON CONFLICT IGNORE);
+    ",
+    // v2: file sizes, filled in by `maintenance::backfill_metadata`
+    "ALTER TABLE posts ADD COLUMN file_size INTEGER;",
+    // v3: tag namespaces, split from names by `tag::split`
+    "
+    CREATE TABLE namespaces (
+    namespace TEXT PRIMARY KEY,
+    color INTEGER,
+    position INTEGER NOT NULL);
+
+    INSERT INTO namespaces (namespace, color, position) VALUES
+    ('artist', 0xE05050, 0),
+    ('series', 0xB050D0, 1),
+    ('character', 0x40B040, 2),
+    ('meta', 0xE09030, 3);
+
+    CREATE TABLE tags_v3 (
+    tag_id INTEGER PRIMARY KEY,
+    namespace TEXT NOT NULL DEFAULT '',
+    tag_name TEXT NOT NULL,
+    full_name TEXT GENERATED ALWAYS AS (
+        CASE namespace WHEN '' THEN tag_name ELSE namespace || ':' || tag_name END
+    ) VIRTUAL,
+    UNIQUE(namespace, tag_name));
+
+    INSERT INTO tags_v3 (tag_id, namespace, tag_name)
+    SELECT tag_id,
+    CASE WHEN instr(tag_name, ':') BETWEEN 2 AND length(tag_name) - 1
+        THEN substr(tag_name, 1, instr(tag_name, ':') - 1) ELSE '' END,
+    CASE WHEN instr(tag_name, ':') BETWEEN 2 AND length(tag_name) - 1
+        THEN substr(tag_name, instr(tag_name, ':') + 1) ELSE tag_name END
+    FROM tags;
+
+    DROP TABLE tags;
+    ALTER TABLE tags_v3 RENAME TO tags;
+    ",
+    // v4: tag aliases
+    "
+    CREATE TABLE tag_aliases (
+    alias TEXT PRIMARY KEY,
+    tag_id INTEGER NOT NULL);
+    ",
+    // v5: tag implications
+    "
+    CREATE TABLE tag_implications (
+    tag_id INTEGER NOT NULL,
+    implied_tag_id INTEGER NOT NULL,
+    UNIQUE(tag_id, implied_tag_id) ON CONFLICT IGNORE);
+    ",
+    // v6: video metadata
+    "
+    ALTER TABLE posts ADD COLUMN width INTEGER;
+    ALTER TABLE posts ADD COLUMN height INTEGER;
+    ALTER TABLE posts ADD COLUMN duration REAL;
+    ALTER TABLE posts ADD COLUMN codec TEXT;
+    ",
+    // v7: MIME types, filled in by `maintenance::backfill_metadata`
+    "ALTER TABLE posts ADD COLUMN mime TEXT;",
+    // v8: perceptual hashes, filled in by `maintenance::fill_phashes`
+    "ALTER TABLE posts ADD COLUMN phash INTEGER;",
+    // v9: hashes of posts merged away, redirecting to the post they became
+    "
+    CREATE TABLE merged_posts (
+    blake3 BLOB PRIMARY KEY,
+    post_id INTEGER NOT NULL);
+    ",
+    // v10: indexes for tag counts and completions
+    "
+    CREATE INDEX taggings_tag_id ON taggings (tag_id);
+    CREATE INDEX tags_full_name ON tags (full_name);
+    ",
+];
+
+/// Selects `implied(tag_id)`: every tag implied by tag `?1`, transitively.
+const IMPLIED_TAGS: &str = "
+    WITH RECURSIVE implied(tag_id) AS (
+        SELECT implied_tag_id FROM tag_implications WHERE tag_id = (?1)
+        UNION
+        SELECT tag_implications.implied_tag_id
+        FROM tag_implications, implied
+        WHERE tag_implications.tag_id = implied.tag_id
+    )";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagCount {
+    pub name: String,
+    pub count: usize,
+}
+
+/// A window into a list of results. The default is every result.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Page {
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+impl Page {
+    pub fn new(offset: usize, limit: usize) -> Self {
+        Page {
+            offset,
+            limit: Some(limit),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Database {
@@ -15,37 +147,93 @@ impl Database {
             Connection::open(&config.db_sql_path).expect("Failed to open the sqlite database?");
         let db = Database { conn, config };
 
-        rusqlite::vtab::array::load_module(&db.conn)
-            .expect("Failed to load virtual tables module?");
+        db.create_functions()
+            .expect("Failed to create database functions?");
 
-        db.create_tables().expect("Failed to create a table?");
+        db.migrate().expect("Failed to migrate the database?");
         db
     }
 
-    fn create_tables(&self) -> Result<()> {
-        self.conn.execute_batch(
-            "
+    fn create_functions(&self) -> Result<()> {
+        // A deterministic shuffle for `order:random`, so every page of a
+        // search sees the same order for the same seed (splitmix64).
+        self.conn.create_scalar_function(
+            "seeded_random",
+            2,
+            FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+            |ctx| {
+                let id = ctx.get::<i64>(0)? as u64;
+                let seed = ctx.get::<i64>(1)? as u64;
+                let mut z = (id ^ seed).wrapping_add(0x9E3779B97F4A7C15);
+                z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+                Ok((z ^ (z >> 31)) as i64)
+            },
+        )
+    }
+
+    fn migrate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let version: usize = self
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        let latest = MIGRATIONS.len();
+
+        if version > latest {
+            return Err(format!(
+                "Database schema v{} is newer than this pkrs supports (v{}). Please update pkrs.",
+                version, latest
+            )
+            .into());
+        }
+
+        if version == latest {
+            return Ok(());
+        }
 
-            CREATE TABLE IF NOT EXISTS posts (
-            post_id INTEGER PRIMARY KEY,
-            blake3 BLOB NOT NULL UNIQUE,
-            extension TEXT,
-            original_name TEXT);
+        if self.has_tables()? {
+            let backup = self.backup(version)?;
+            println!(
+                "Migrating database v{} -> v{}. Backup saved to {}",
+                version, latest, backup
+            );
+        }
 
-            CREATE TABLE IF NOT EXISTS tags (
-            tag_id INTEGER PRIMARY KEY,
-            tag_name TEXT NOT NULL UNIQUE);
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            self.begin()?;
+            let result = self
+                .conn
+                .execute_batch(migration)
+                .and_then(|_| self.conn.pragma_update(None, "user_version", index + 1));
 
-            CREATE TABLE IF NOT EXISTS taggings (
-            tagging_id INTEGER PRIMARY KEY,
-            post_id INTEGER NOT NULL,
-            tag_id INTEGER NOT NULL,
-            UNIQUE(post_id, tag_id) ON CONFLICT IGNORE);
+            if let Err(e) = result {
+                self.rollback()?;
+                return Err(format!("Failed to migrate database to v{}: {}", index + 1, e).into());
+            }
+            self.commit()?;
+        }
 
-        ",
+        Ok(())
+    }
+
+    fn has_tables(&self) -> Result<bool> {
+        self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table')",
+            [],
+            |row| row.get(0),
         )
     }
 
+    fn backup(&self, version: usize) -> Result<String> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let path = format!("{}.v{}-{}.bak", self.config.db_sql_path, version, timestamp);
+
+        self.conn.execute("VACUUM INTO (?1)", [&path])?;
+        Ok(path)
+    }
+
     pub fn begin(&self) -> Result<()> {
         self.conn.execute_batch("BEGIN TRANSACTION;")
     }
@@ -54,12 +242,32 @@ impl Database {
         self.conn.execute_batch("COMMIT TRANSACTION;")
     }
 
+    pub fn rollback(&self) -> Result<()> {
+        self.conn.execute_batch("ROLLBACK TRANSACTION;")
+    }
+
+    /// Returns the new post's id, or 0 if the file is already a post.
     pub fn insert_post(&self, post: &Post) -> Result<i64, Error> {
-        self.conn.prepare_cached(
-            "INSERT OR IGNORE INTO posts (blake3, extension, original_name) VALUES (?1, ?2, ?3)")?
-            .execute((&post.blake3_bytes, &post.extension, &post.original_name))?;
+        let inserted = self.conn.prepare_cached(
+            "INSERT OR IGNORE INTO posts (blake3, extension, original_name, file_size, mime, width, height, duration, codec, phash)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)")?
+            .execute((
+                &post.blake3_bytes,
+                &post.extension,
+                &post.original_name,
+                &post.file_size,
+                &post.mime,
+                &post.width,
+                &post.height,
+                &post.duration,
+                &post.codec,
+                &post.phash,
+            ))?;
 
-        Ok(self.conn.last_insert_rowid())
+        match inserted {
+            0 => Ok(0),
+            _ => Ok(self.conn.last_insert_rowid()),
+        }
     }
 
     pub fn remove_post(&self, post_id: i64) -> Result<(), Error> {
@@ -71,18 +279,75 @@ impl Database {
             .prepare_cached("DELETE FROM taggings WHERE post_id = (?1)")?
             .execute([post_id])?;
 
+        self.conn
+            .prepare_cached("DELETE FROM merged_posts WHERE post_id = (?1)")?
+            .execute([post_id])?;
+
+        Ok(())
+    }
+
+    /// Points `post` at a different file, keeping its id and tags.
+    pub fn update_post_file(&self, post: &Post) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                "UPDATE posts SET blake3 = (?1), extension = (?2), original_name = (?3),
+                file_size = (?4), mime = (?5), width = (?6), height = (?7),
+                duration = (?8), codec = (?9), phash = (?10)
+                WHERE post_id = (?11)",
+            )?
+            .execute((
+                &post.blake3_bytes,
+                &post.extension,
+                &post.original_name,
+                &post.file_size,
+                &post.mime,
+                &post.width,
+                &post.height,
+                &post.duration,
+                &post.codec,
+                &post.phash,
+                &post.id,
+            ))?;
+        Ok(())
+    }
+
+    /// Records that the file `blake3_bytes` was merged into `post_id`, and
+    /// moves anything already merged into `from` along with it.
+    pub fn insert_merged_post(
+        &self,
+        blake3_bytes: [u8; 32],
+        from: i64,
+        post_id: i64,
+    ) -> Result<(), Error> {
+        self.conn
+            .prepare_cached("UPDATE merged_posts SET post_id = (?1) WHERE post_id = (?2)")?
+            .execute([post_id, from])?;
+
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO merged_posts (blake3, post_id) VALUES (?1, ?2)",
+            )?
+            .execute((blake3_bytes, post_id))?;
         Ok(())
     }
 
-    pub fn insert_tag(&self, name: &String) -> Result<i64, Error> {
+    /// The post a file was merged into, if it was.
+    pub fn get_merged_post(&self, blake3_bytes: [u8; 32]) -> Result<Option<i64>, Error> {
         self.conn
-            .prepare_cached("INSERT OR IGNORE INTO tags (tag_name) VALUES (?1)")?
-            .execute([name])?;
+            .prepare_cached("SELECT post_id FROM merged_posts WHERE blake3 = (?1)")?
+            .query_row([blake3_bytes], |row| row.get(0))
+            .optional()
+    }
+
+    pub fn insert_tag(&self, name: &str) -> Result<i64, Error> {
+        self.conn
+            .prepare_cached("INSERT OR IGNORE INTO tags (namespace, tag_name) VALUES (?1, ?2)")?
+            .execute(tag::split(name))?;
 
         Ok(self.conn.last_insert_rowid())
     }
 
-    pub fn remove_tag(&self, tag_name: &String) -> Result<i64, Error> {
+    pub fn remove_tag(&self, tag_name: &str) -> Result<i64, Error> {
         let tag_id = self.get_tag_id(tag_name)?;
 
         self.conn
@@ -93,6 +358,16 @@ impl Database {
             .prepare_cached("DELETE FROM taggings WHERE tag_id = (?1)")?
             .execute([tag_id])?;
 
+        self.conn
+            .prepare_cached("DELETE FROM tag_aliases WHERE tag_id = (?1)")?
+            .execute([tag_id])?;
+
+        self.conn
+            .prepare_cached(
+                "DELETE FROM tag_implications WHERE tag_id = (?1) OR implied_tag_id = (?1)",
+            )?
+            .execute([tag_id])?;
+
         Ok(tag_id)
     }
 
@@ -125,32 +400,373 @@ impl Database {
     }
 
     fn row_to_post(&self, row: &Row) -> Result<Post, Error> {
-        let post_id = row.get(0)?;
+        let post_id = row.get("post_id")?;
         Ok(Post {
             id: post_id,
-            blake3_bytes: row.get(1)?,
-            extension: row.get(2)?,
-            original_name: row.get(3)?,
+            blake3_bytes: row.get("blake3")?,
+            extension: row.get("extension")?,
+            original_name: row.get("original_name")?,
+            file_size: row.get("file_size")?,
+            mime: row
+                .get::<_, Option<String>>("mime")?
+                .filter(|m| !m.is_empty()),
+            width: row.get("width")?,
+            height: row.get("height")?,
+            duration: row.get("duration")?,
+            codec: row.get("codec")?,
+            phash: row.get("phash")?,
             tags: self.get_post_tags(post_id)?,
         })
     }
 
-    pub fn get_tag_id(&self, name: &String) -> Result<i64, Error> {
+    /// Posts imported before sizes, dimensions and MIME types were recorded.
+    pub fn get_posts_without_metadata(&self) -> Result<Vec<Post>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT * FROM posts WHERE mime IS NULL")?;
+        let posts = stmt.query_map([], |row| self.row_to_post(row))?;
+        posts.collect()
+    }
+
+    /// Fills in what's missing. Files that can't be read get an empty MIME
+    /// type, so they aren't read again.
+    pub fn set_metadata(
+        &self,
+        post_id: i64,
+        file_size: Option<i64>,
+        metadata: &Metadata,
+    ) -> Result<(), Error> {
         self.conn
-            .prepare_cached("SELECT tag_id FROM tags WHERE (tag_name) = (?1)")?
-            .query_row([name], |row| row.get(0))
+            .prepare_cached(
+                "UPDATE posts SET file_size = coalesce(file_size, ?1), mime = coalesce(?2, ''),
+                width = coalesce(width, ?3), height = coalesce(height, ?4),
+                duration = coalesce(duration, ?5), codec = coalesce(codec, ?6)
+                WHERE post_id = (?7)",
+            )?
+            .execute((
+                file_size,
+                &metadata.mime,
+                metadata.width,
+                metadata.height,
+                metadata.duration,
+                &metadata.codec,
+                post_id,
+            ))?;
+        Ok(())
+    }
+
+    /// Images without a perceptual hash, and posts whose MIME type hasn't
+    /// been read yet, which may be images.
+    pub fn get_unhashed_images(&self) -> Result<Vec<Post>, Error> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT * FROM posts WHERE phash IS NULL AND (mime IS NULL OR mime LIKE 'image/%')",
+        )?;
+        let posts = stmt.query_map([], |row| self.row_to_post(row))?;
+        posts.collect()
+    }
+
+    pub fn set_phash(&self, post_id: i64, phash: i64) -> Result<(), Error> {
+        self.conn
+            .prepare_cached("UPDATE posts SET phash = (?1) WHERE post_id = (?2)")?
+            .execute((phash, post_id))?;
+        Ok(())
     }
 
-    pub fn get_or_create_tag(&self, name: &String) -> Result<i64, Error> {
+    /// Every post's perceptual hash, ordered by id.
+    pub fn get_phashes(&self) -> Result<Vec<(i64, u64)>, Error> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT post_id, phash FROM posts WHERE phash IS NOT NULL ORDER BY post_id",
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as u64)))?;
+        rows.collect()
+    }
+
+    pub fn get_tag_id(&self, name: &str) -> Result<i64, Error> {
+        self.conn
+            .prepare_cached("SELECT tag_id FROM tags WHERE (namespace, tag_name) = (?1, ?2)")?
+            .query_row(tag::split(name), |row| row.get(0))
+    }
+
+    pub fn get_or_create_tag(&self, name: &str) -> Result<i64, Error> {
+        if let Some(tag_id) = self.get_alias_tag_id(name)? {
+            return Ok(tag_id);
+        }
+
         match self.get_tag_id(name) {
             Ok(existing) => Ok(existing),
             Err(_) => self.insert_tag(name),
         }
     }
 
+    pub fn get_tag_name(&self, tag_id: i64) -> Result<String, Error> {
+        self.conn
+            .prepare_cached("SELECT full_name FROM tags WHERE tag_id = (?1)")?
+            .query_row([tag_id], |row| row.get(0))
+    }
+
+    /// Returns the tag `name` is an alias of, or `name` itself.
+    pub fn canonical_tag(&self, name: &str) -> Result<String, Error> {
+        match self.get_alias_tag_id(name)? {
+            Some(tag_id) => self.get_tag_name(tag_id),
+            None => Ok(name.to_string()),
+        }
+    }
+
+    fn get_alias_tag_id(&self, alias: &str) -> Result<Option<i64>, Error> {
+        self.conn
+            .prepare_cached("SELECT tag_id FROM tag_aliases WHERE alias = (?1)")?
+            .query_row([alias], |row| row.get(0))
+            .optional()
+    }
+
+    pub fn insert_alias(&self, alias: &str, tag_id: i64) -> Result<(), Error> {
+        self.conn
+            .prepare_cached("INSERT OR REPLACE INTO tag_aliases (alias, tag_id) VALUES (?1, ?2)")?
+            .execute((alias, tag_id))?;
+        Ok(())
+    }
+
+    pub fn remove_alias(&self, alias: &str) -> Result<usize, Error> {
+        self.conn
+            .prepare_cached("DELETE FROM tag_aliases WHERE alias = (?1)")?
+            .execute([alias])
+    }
+
+    /// Returns every `(alias, tag)` pair, sorted by tag.
+    pub fn get_aliases(&self) -> Result<Vec<(String, String)>, Error> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT tag_aliases.alias, tags.full_name
+            FROM tag_aliases, tags
+            WHERE tag_aliases.tag_id = tags.tag_id
+            ORDER BY tags.full_name, tag_aliases.alias",
+        )?;
+
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let mut aliases = Vec::new();
+        for alias in rows {
+            aliases.push(alias?);
+        }
+        Ok(aliases)
+    }
+
+    /// Returns every `(tag_id, name)` implied by `tag_id`, transitively.
+    pub fn get_implied_tags(&self, tag_id: i64) -> Result<Vec<(i64, String)>, Error> {
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "{}
+            SELECT tags.tag_id, tags.full_name
+            FROM tags, implied
+            WHERE tags.tag_id = implied.tag_id",
+            IMPLIED_TAGS
+        ))?;
+
+        let rows = stmt.query_map([tag_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let mut tags = Vec::new();
+        for tag in rows {
+            tags.push(tag?);
+        }
+        Ok(tags)
+    }
+
+    /// Whether `tag_id` implies `implied_tag_id`, directly or transitively.
+    pub fn implies(&self, tag_id: i64, implied_tag_id: i64) -> Result<bool, Error> {
+        self.conn
+            .prepare_cached(&format!(
+                "{} SELECT EXISTS (SELECT 1 FROM implied WHERE tag_id = (?2))",
+                IMPLIED_TAGS
+            ))?
+            .query_row([tag_id, implied_tag_id], |row| row.get(0))
+    }
+
+    pub fn insert_implication(&self, tag_id: i64, implied_tag_id: i64) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                "INSERT INTO tag_implications (tag_id, implied_tag_id) VALUES (?1, ?2)",
+            )?
+            .execute([tag_id, implied_tag_id])?;
+        Ok(())
+    }
+
+    pub fn remove_implication(&self, tag_id: i64, implied_tag_id: i64) -> Result<usize, Error> {
+        self.conn
+            .prepare_cached(
+                "DELETE FROM tag_implications WHERE tag_id = (?1) AND implied_tag_id = (?2)",
+            )?
+            .execute([tag_id, implied_tag_id])
+    }
+
+    /// Returns every direct `(tag, implied tag)` pair, sorted by tag.
+    pub fn get_implications(&self) -> Result<Vec<(String, String)>, Error> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT tag.full_name, implied.full_name
+            FROM tag_implications, tags AS tag, tags AS implied
+            WHERE tag_implications.tag_id = tag.tag_id
+            AND tag_implications.implied_tag_id = implied.tag_id
+            ORDER BY tag.full_name, implied.full_name",
+        )?;
+
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let mut implications = Vec::new();
+        for implication in rows {
+            implications.push(implication?);
+        }
+        Ok(implications)
+    }
+
+    /// Tags every post tagged with `tag_id` with the tags it implies. Returns
+    /// how many taggings were added.
+    pub fn apply_implications(&self, tag_id: i64) -> Result<usize, Error> {
+        self.conn
+            .prepare_cached(&format!(
+                "{}
+                INSERT OR IGNORE INTO taggings (post_id, tag_id)
+                SELECT taggings.post_id, implied.tag_id
+                FROM taggings, implied
+                WHERE taggings.tag_id = (?1)",
+                IMPLIED_TAGS
+            ))?
+            .execute([tag_id])
+    }
+
+    /// Renames `tag_id` to `name`, merging it into `name` if that tag already
+    /// exists. Returns how many posts are tagged with the renamed tag.
+    pub fn rename_tag(&self, tag_id: i64, name: &str) -> Result<usize, Error> {
+        match self.get_tag_id(name) {
+            Ok(existing) if existing == tag_id => Ok(0),
+            Ok(existing) => self.merge_tags(tag_id, existing),
+            Err(Error::QueryReturnedNoRows) => {
+                let (namespace, tag_name) = tag::split(name);
+                self.conn
+                    .prepare_cached(
+                        "UPDATE tags SET namespace = (?2), tag_name = (?3) WHERE tag_id = (?1)",
+                    )?
+                    .execute((tag_id, namespace, tag_name))?;
+                self.count_tag_posts(tag_id)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns tags starting with `prefix` and how many posts use them, most
+    /// used first when `by_count` is set, otherwise by name. The prefix is
+    /// matched as a range so `tags_full_name` can be used; no UTF-8 text
+    /// contains the byte `ff`.
+    pub fn get_tag_counts(
+        &self,
+        prefix: &str,
+        by_count: bool,
+        page: &Page,
+    ) -> Result<Vec<TagCount>, Error> {
+        let order_by = match by_count {
+            true => "count DESC, tags.full_name",
+            false => "tags.full_name",
+        };
+
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT tags.full_name, COUNT(taggings.post_id) AS count
+            FROM tags LEFT JOIN taggings ON taggings.tag_id = tags.tag_id
+            WHERE tags.full_name >= (?1) AND tags.full_name < (?1) || x'ff'
+            GROUP BY tags.full_name
+            ORDER BY {}
+            LIMIT (?2) OFFSET (?3)",
+            order_by
+        ))?;
+
+        let limit = page.limit.map_or(-1, |limit| limit as i64);
+        let rows = stmt.query_map((prefix, limit, page.offset as i64), |row| {
+            Ok(TagCount {
+                name: row.get(0)?,
+                count: row.get(1)?,
+            })
+        })?;
+
+        let mut tags = Vec::new();
+        for tag in rows {
+            tags.push(tag?);
+        }
+        Ok(tags)
+    }
+
+    /// The `limit` most used tags starting with `prefix`.
+    pub fn complete_tags(&self, prefix: &str, limit: usize) -> Result<Vec<TagCount>, Error> {
+        self.get_tag_counts(prefix, true, &Page::new(0, limit))
+    }
+
+    /// Tags on no posts. Tags an alias or implication points to are kept.
+    pub fn get_orphan_tags(&self) -> Result<Vec<String>, Error> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT full_name FROM tags
+            WHERE tag_id NOT IN (SELECT tag_id FROM taggings)
+            AND tag_id NOT IN (SELECT tag_id FROM tag_aliases)
+            AND tag_id NOT IN (SELECT tag_id FROM tag_implications)
+            AND tag_id NOT IN (SELECT implied_tag_id FROM tag_implications)
+            ORDER BY full_name",
+        )?;
+
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        let mut tags = Vec::new();
+        for tag in rows {
+            tags.push(tag?);
+        }
+        Ok(tags)
+    }
+
+    pub fn count_tag_posts(&self, tag_id: i64) -> Result<usize, Error> {
+        self.conn
+            .prepare_cached("SELECT COUNT(*) FROM taggings WHERE tag_id = (?1)")?
+            .query_row([tag_id], |row| row.get(0))
+    }
+
+    /// Moves every tagging of `from` onto `into` and deletes `from`. Returns
+    /// how many posts were tagged with `from`.
+    pub fn merge_tags(&self, from: i64, into: i64) -> Result<usize, Error> {
+        let count = self.count_tag_posts(from)?;
+
+        self.conn
+            .prepare_cached("UPDATE OR IGNORE taggings SET tag_id = (?2) WHERE tag_id = (?1)")?
+            .execute([from, into])?;
+
+        // Posts that already had `into` keep their old tagging; drop it.
+        self.conn
+            .prepare_cached("DELETE FROM taggings WHERE tag_id = (?1)")?
+            .execute([from])?;
+
+        self.conn
+            .prepare_cached("UPDATE tag_aliases SET tag_id = (?2) WHERE tag_id = (?1)")?
+            .execute([from, into])?;
+
+        for column in ["tag_id", "implied_tag_id"] {
+            self.conn
+                .prepare_cached(&format!(
+                    "UPDATE OR IGNORE tag_implications SET {0} = (?2) WHERE {0} = (?1)",
+                    column
+                ))?
+                .execute([from, into])?;
+
+            self.conn
+                .prepare_cached(&format!(
+                    "DELETE FROM tag_implications WHERE {} = (?1)",
+                    column
+                ))?
+                .execute([from])?;
+        }
+
+        // Merging a tag into one it implied leaves a self-implication.
+        self.conn
+            .prepare_cached("DELETE FROM tag_implications WHERE tag_id = implied_tag_id")?
+            .execute([])?;
+
+        self.apply_implications(into)?;
+
+        self.conn
+            .prepare_cached("DELETE FROM tags WHERE tag_id = (?1)")?
+            .execute([from])?;
+
+        Ok(count)
+    }
+
     pub fn get_post_tags(&self, post_id: i64) -> Result<HashSet<String>, Error> {
         let mut stmt = self.conn.prepare_cached(
-            "SELECT tags.tag_name
+            "SELECT tags.full_name
             FROM tags, taggings
             WHERE tags.tag_id = taggings.tag_id
             AND taggings.post_id = (?1)",
@@ -165,38 +781,65 @@ impl Database {
         Ok(tags)
     }
 
-    fn to_rc_vec(vec: Vec<String>) -> Rc<Vec<Value>> {
-        Rc::new(
-            vec.iter()
-                .map(String::from)
-                .map(Value::from)
-                .collect::<Vec<Value>>(),
-        )
+    pub fn get_namespaces(&self) -> Result<Vec<Namespace>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT namespace, color FROM namespaces ORDER BY position")?;
+
+        let rows = stmt.query_map([], |row| {
+            Ok(Namespace {
+                name: row.get(0)?,
+                color: row.get(1)?,
+            })
+        })?;
+
+        let mut namespaces = Vec::new();
+        for namespace in rows {
+            namespaces.push(namespace?);
+        }
+        Ok(namespaces)
     }
 
-    pub fn search(&self, include: Vec<String>, exclude: Vec<String>) -> Result<Vec<Post>, Error> {
-        let include = Self::to_rc_vec(include);
-        let exclude = Self::to_rc_vec(exclude);
-        let tag_count = include.len();
+    pub fn insert_namespace(&self, name: &String, color: Option<u32>) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                "INSERT INTO namespaces (namespace, color, position)
+                VALUES (?1, ?2, (SELECT IFNULL(MAX(position) + 1, 0) FROM namespaces))
+                ON CONFLICT (namespace) DO UPDATE SET color = excluded.color",
+            )?
+            .execute((name, color))?;
+        Ok(())
+    }
 
-        let mut stmt = self.conn.prepare_cached(
+    pub fn remove_namespace(&self, name: &String) -> Result<usize, Error> {
+        self.conn
+            .prepare_cached("DELETE FROM namespaces WHERE namespace = (?1)")?
+            .execute([name])
+    }
+
+    /// Returns a page of the posts matching `filter`, an SQL condition over
+    /// `posts` compiled by [`crate::search::Expr::to_sql`], sorted by
+    /// `order_by`. `params` binds the placeholders of both, in that order.
+    pub fn search(
+        &self,
+        filter: &str,
+        order_by: &str,
+        mut params: Vec<Value>,
+        page: &Page,
+    ) -> Result<Vec<Post>, Error> {
+        let mut stmt = self.conn.prepare_cached(&format!(
             "SELECT posts.*
-            FROM posts, taggings, tags
-            WHERE taggings.tag_id = tags.tag_id
-            AND (tags.tag_name IN rarray(?1))
-            AND posts.post_id NOT IN (
-                SELECT posts.post_id
-                FROM posts, taggings, tags
-                WHERE posts.post_id = taggings.post_id
-                AND taggings.tag_id = tags.tag_id
-                AND (tags.tag_name IN rarray(?2))
-            )
-            AND posts.post_id = taggings.post_id
-            GROUP BY posts.post_id
-            HAVING COUNT(posts.post_id) = (?3)",
-        )?;
+            FROM posts
+            WHERE {}
+            ORDER BY {}
+            LIMIT ? OFFSET ?",
+            filter, order_by
+        ))?;
 
-        let rows = stmt.query_map((include, exclude, tag_count), |row| self.row_to_post(row))?;
+        params.push(Value::from(page.limit.map_or(-1, |limit| limit as i64)));
+        params.push(Value::from(page.offset as i64));
+
+        let rows = stmt.query_map(params_from_iter(params), |row| self.row_to_post(row))?;
         let mut posts = Vec::new();
         for post in rows {
             posts.push(post?);
@@ -204,17 +847,201 @@ impl Database {
 
         Ok(posts)
     }
+}
 
-    pub fn all(&self) -> Result<Vec<Post>, Error> {
-        let mut stmt = self
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    pub fn database() -> Database {
+        let config = toml::from_str(r#"db_sql_path = ":memory:""#).unwrap();
+        Database::connect(config)
+    }
+
+    /// Inserts an untagged post whose hash is `id` repeated.
+    pub fn insert_post(id: u8, db: &Database) -> Post {
+        let mut post = Post {
+            id: 0,
+            blake3_bytes: [id; 32],
+            extension: None,
+            original_name: format!("{}.png", id),
+            file_size: None,
+            mime: None,
+            width: None,
+            height: None,
+            duration: None,
+            codec: None,
+            phash: None,
+            tags: HashSet::new(),
+        };
+        post.id = db.insert_post(&post).unwrap();
+        post
+    }
+
+    #[test]
+    fn removing_a_tag_removes_its_aliases_and_implications() {
+        let db = database();
+        let mut post = insert_post(1, &db);
+        let monochrome = db.insert_tag("monochrome").unwrap();
+        let sketch = db.insert_tag("sketch").unwrap();
+        let art = db.insert_tag("art").unwrap();
+        db.insert_alias("bw", monochrome).unwrap();
+        db.insert_implication(sketch, monochrome).unwrap();
+        db.insert_implication(monochrome, art).unwrap();
+
+        db.remove_tag("monochrome").unwrap();
+        assert!(db.get_aliases().unwrap().is_empty());
+        assert!(db.get_implications().unwrap().is_empty());
+
+        assert_eq!(db.canonical_tag("bw").unwrap(), "bw");
+        post.add_tag("bw", &db).unwrap();
+        post.add_tag("sketch", &db).unwrap();
+        let tags = HashSet::from(["bw".to_string(), "sketch".to_string()]);
+        assert_eq!(post.tags, tags);
+        assert_eq!(db.get_post_tags(post.id).unwrap(), tags);
+    }
+
+    #[test]
+    fn tag_counts_match_prefixes() {
+        let db = database();
+        let mut post = insert_post(1, &db);
+        for tag in ["cat", "car", "character:cat", "dog", "é"] {
+            post.add_tag(tag, &db).unwrap();
+        }
+        insert_post(2, &db).add_tag("cat", &db).unwrap();
+
+        let names = |prefix: &str| -> Vec<String> {
+            let tags = db.get_tag_counts(prefix, true, &Page::default()).unwrap();
+            tags.into_iter().map(|tag| tag.name).collect()
+        };
+        assert_eq!(names("ca"), ["cat", "car"]);
+        assert_eq!(names("character:"), ["character:cat"]);
+        assert_eq!(names("é"), ["é"]);
+        assert_eq!(names("").len(), 5);
+        assert!(names("x").is_empty());
+    }
+
+    fn tags(post: &Post, db: &Database) -> Vec<String> {
+        let mut tags: Vec<_> = db.get_post_tags(post.id).unwrap().into_iter().collect();
+        tags.sort();
+        tags
+    }
+
+    #[test]
+    fn merging_tags_that_share_posts() {
+        let db = database();
+        let mut both = insert_post(1, &db);
+        let mut only_from = insert_post(2, &db);
+        both.add_tag("kitty", &db).unwrap();
+        both.add_tag("cat", &db).unwrap();
+        only_from.add_tag("kitty", &db).unwrap();
+
+        let kitty = db.get_tag_id("kitty").unwrap();
+        let cat = db.get_tag_id("cat").unwrap();
+        assert_eq!(db.merge_tags(kitty, cat).unwrap(), 2);
+
+        assert_eq!(tags(&both, &db), ["cat"]);
+        assert_eq!(tags(&only_from, &db), ["cat"]);
+        assert_eq!(db.count_tag_posts(cat).unwrap(), 2);
+        assert!(db.get_tag_id("kitty").is_err());
+    }
+
+    #[test]
+    fn merging_tags_with_aliases_and_implications() {
+        let db = database();
+        let kitty = db.insert_tag("kitty").unwrap();
+        let cat = db.insert_tag("cat").unwrap();
+        let [pet, animal, kitten] = ["pet", "animal", "kitten"].map(|t| db.insert_tag(t).unwrap());
+        db.insert_alias("kitteh", kitty).unwrap();
+        db.insert_alias("neko", cat).unwrap();
+        db.insert_implication(kitty, pet).unwrap();
+        db.insert_implication(kitty, animal).unwrap();
+        db.insert_implication(cat, animal).unwrap();
+        db.insert_implication(kitten, kitty).unwrap();
+        db.insert_implication(kitty, cat).unwrap();
+
+        let mut post = insert_post(1, &db);
+        post.add_tag("kitty", &db).unwrap();
+        db.merge_tags(kitty, cat).unwrap();
+
+        let aliases = [("kitteh", "cat"), ("neko", "cat")].map(|(a, t)| (a.into(), t.into()));
+        assert_eq!(db.get_aliases().unwrap(), aliases);
+
+        let implications = [("cat", "animal"), ("cat", "pet"), ("kitten", "cat")]
+            .map(|(t, i)| (t.into(), i.into()));
+        assert_eq!(db.get_implications().unwrap(), implications);
+
+        // The post gets what `cat` now implies.
+        assert_eq!(tags(&post, &db), ["animal", "cat", "pet"]);
+    }
+
+    #[test]
+    fn renaming_onto_an_existing_tag_merges_them() {
+        let db = database();
+        let mut post = insert_post(1, &db);
+        let mut other = insert_post(2, &db);
+        post.add_tag("colour", &db).unwrap();
+        post.add_tag("color", &db).unwrap();
+        other.add_tag("colour", &db).unwrap();
+
+        let colour = db.get_tag_id("colour").unwrap();
+        assert_eq!(db.rename_tag(colour, "color").unwrap(), 2);
+        assert_eq!(tags(&post, &db), ["color"]);
+        assert_eq!(tags(&other, &db), ["color"]);
+        assert!(db.get_tag_id("colour").is_err());
+
+        // Renaming to a new name keeps the id, and to itself does nothing.
+        let color = db.get_tag_id("color").unwrap();
+        assert_eq!(db.rename_tag(color, "meta:colour").unwrap(), 2);
+        assert_eq!(db.get_tag_id("meta:colour").unwrap(), color);
+        assert_eq!(db.rename_tag(color, "meta:colour").unwrap(), 0);
+    }
+
+    #[test]
+    fn migrating_v1_splits_namespaces_and_keeps_full_names() {
+        // Migrating backs the database up next to it, so it can't be in memory.
+        let dir = std::env::temp_dir().join(format!("pkrs-migrate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("v1.db").to_string_lossy().to_string();
+
+        let names = ["plain", "artist:someone", "a:b:c", ":smile", "wink:", "::"];
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        conn.pragma_update(None, "user_version", 1).unwrap();
+        for name in names {
+            conn.execute("INSERT INTO tags (tag_name) VALUES (?1)", [name])
+                .unwrap();
+        }
+        conn.execute("INSERT INTO taggings (post_id, tag_id) VALUES (1, 3)", [])
+            .unwrap();
+        drop(conn);
+
+        let config = toml::from_str(&format!("db_sql_path = {:?}", path)).unwrap();
+        let db = Database::connect(config);
+        let version: usize = db
             .conn
-            .prepare_cached("SELECT * FROM posts ORDER BY post_id DESC")?;
-        let rows = stmt.query_map([], |row| self.row_to_post(row))?;
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
 
-        let mut posts = Vec::new();
-        for post in rows {
-            posts.push(post?);
+        for name in names {
+            let (namespace, tag_name): (String, String) = db
+                .conn
+                .query_row(
+                    "SELECT namespace, tag_name FROM tags WHERE full_name = (?1)",
+                    [name],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .unwrap();
+            assert_eq!((namespace.as_str(), tag_name.as_str()), tag::split(name));
+            assert_eq!(db.get_tag_name(db.get_tag_id(name).unwrap()).unwrap(), name);
         }
-        Ok(posts)
+        assert_eq!(
+            db.get_post_tags(1).unwrap(),
+            HashSet::from(["a:b:c".to_string()])
+        );
+
+        drop(db);
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -10,19 +10,24 @@ use std::{
 
 use crate::{
     config::Config,
-    db::Database,
+    db::{Database, TagCount},
+    import::{self, FolderTags},
+    maintenance,
     message::{FromGUI, FromWorker},
-    post::Post,
-    thumbnail,
+    post::{self, Post},
+    tag::{self, Namespace},
+    viewer::{self, Action, Viewer},
     worker::Worker,
 };
-use eframe::egui::{self, Context, Key, Visuals};
+use eframe::egui::{
+    self,
+    text::{CCursor, CCursorRange},
+    text_edit::TextEditState,
+    AboveOrBelow, Context, DroppedFile, Key, Visuals,
+};
 use egui_extras::{Column, TableBuilder};
 use poll_promise::Promise;
 
-static THUMBNAIL_SIZE: f32 = thumbnail::THUMBNAIL_SIZE as f32;
-static THUMBNAIL_VEC2: [f32; 2] = [THUMBNAIL_SIZE, THUMBNAIL_SIZE];
-
 pub fn run(db: Database) -> Result<(), eframe::Error> {
     let (from_worker, to_gui) = mpsc::channel::<FromWorker>();
     let (from_gui, to_worker) = mpsc::channel::<FromGUI>();
@@ -38,20 +43,41 @@ impl App {
         rx: Receiver<FromWorker>,
         config: Config,
     ) -> Result<(), eframe::Error> {
+        let thumbnail_sizes = config.thumbnail_sizes();
+        let zoom = thumbnail_sizes
+            .iter()
+            .position(|&size| size == config.thumbnail_size)
+            .unwrap_or_default();
+
         let app = App {
             tx,
             rx,
             config,
+            thumbnail_sizes,
             posts: vec![],
+            more_posts: false,
+            requested_more: false,
             progress: (0.0, 0.0),
             show_progress: false,
             progress_message: None,
             search: String::new(),
+            search_error: None,
+            error: None,
+            warnings: Vec::new(),
+            renaming: None,
+            viewer: None,
+            duplicates: None,
+            import: Default::default(),
+            namespaces: vec![],
             selected: None,
             tag_editor: None,
             focus_search: false,
             focus_editor: false,
-            settings: Default::default(),
+            completions: Default::default(),
+            settings: AppSettings {
+                zoom,
+                ..Default::default()
+            },
         };
 
         let options = eframe::NativeOptions {
@@ -74,23 +100,74 @@ pub struct App {
     tx: Sender<FromGUI>,
     rx: Receiver<FromWorker>,
     config: Config,
+    /// `Config::thumbnail_sizes`, which the grid can be zoomed between.
+    thumbnail_sizes: Vec<u32>,
     posts: Vec<PostThumbnail>,
+    more_posts: bool,
+    requested_more: bool,
     progress: (f32, f32),
     show_progress: bool,
     progress_message: Option<String>,
     search: String,
+    search_error: Option<String>,
+    /// A request the worker failed, shown until dismissed.
+    error: Option<String>,
+    /// Things a request did that may not be what was meant, like removing a
+    /// tag that's still implied.
+    warnings: Vec<String>,
+    renaming: Option<(String, String)>,
+    viewer: Option<Viewer>,
+    duplicates: Option<Duplicates>,
+    import: ImportDialog,
+    namespaces: Vec<Namespace>,
     selected: Option<usize>,
     tag_editor: Option<String>,
     focus_search: bool,
     focus_editor: bool,
+    completions: Completions,
     settings: AppSettings,
 }
 
+/// Groups of near-duplicate posts, compared one group at a time.
+struct Duplicates {
+    groups: Vec<Vec<PostThumbnail>>,
+    current: usize,
+    threshold: u32,
+}
+
+/// Asks how to tag dropped or picked files before importing them. The options
+/// are kept for the next import.
+#[derive(Default)]
+struct ImportDialog {
+    pending: Option<PendingImport>,
+    tags: String,
+    folder_tags: bool,
+    namespace: String,
+    /// 0 for every folder.
+    depth: usize,
+}
+
+enum PendingImport {
+    Dropped(Vec<DroppedFile>),
+    Picked(Vec<PathBuf>),
+}
+
+impl PendingImport {
+    fn paths(&self) -> Vec<&PathBuf> {
+        match self {
+            PendingImport::Dropped(files) => files.iter().filter_map(|f| f.path.as_ref()).collect(),
+            PendingImport::Picked(paths) => paths.iter().collect(),
+        }
+    }
+}
+
 struct AppSettings {
     window_size: (f32, f32),
     main_panel_width: f32,
     fullscreen: bool,
     dark_mode: bool,
+    /// Index into `App::thumbnail_sizes`.
+    zoom: usize,
 }
 
 impl Default for AppSettings {
@@ -102,6 +179,7 @@ impl Default for AppSettings {
             main_panel_width: width * 0.8,
             fullscreen: false,
             dark_mode: true,
+            zoom: 0,
         }
     }
 }
@@ -112,8 +190,16 @@ impl App {
             FromWorker::RequestContext => self.tx.send(FromGUI::SendContext(ctx.clone()))?,
             FromWorker::SetPosts(posts) => {
                 self.posts = posts;
+                self.more_posts = true;
+                self.requested_more = false;
                 self.selected = None;
                 self.tag_editor = None;
+                self.viewer = None;
+            }
+            FromWorker::AppendPosts(posts) => {
+                self.more_posts = !posts.is_empty();
+                self.requested_more = false;
+                self.posts.extend(posts);
             }
             FromWorker::ShowProgress(b) => self.show_progress = b,
             FromWorker::SetProgress(current, total) => self.progress = (current, total),
@@ -122,6 +208,40 @@ impl App {
                 self.selected = selected;
                 self.tag_editor = None;
             }
+            FromWorker::SetSearchError(error) => self.search_error = error,
+            FromWorker::SetNamespaces(namespaces) => self.namespaces = namespaces,
+            FromWorker::RenamedTag(old, new) => {
+                for thumbnail in &mut self.posts {
+                    if thumbnail.post.tags.remove(&old) {
+                        thumbnail.post.tags.insert(new.clone());
+                    }
+                }
+            }
+            FromWorker::SetCompletions(prefix, tags) => {
+                if prefix == self.completions.prefix {
+                    self.completions.tags = tags;
+                    self.completions.open = true;
+                }
+            }
+            FromWorker::SetDuplicates(groups) => {
+                if let Some(duplicates) = &mut self.duplicates {
+                    duplicates.groups = groups;
+                    duplicates.current = 0;
+                }
+            }
+            FromWorker::ShowError(error) => self.error = Some(error),
+            FromWorker::ShowWarnings(warnings) => self.warnings.extend(warnings),
+            FromWorker::RemovedPosts(post_ids) => {
+                self.posts.retain(|t| !post_ids.contains(&t.post.id));
+                self.selected = None;
+                self.tag_editor = None;
+                self.viewer = None;
+            }
+            FromWorker::SetPostTags(post_id, tags) => {
+                if let Some(thumbnail) = self.posts.iter_mut().find(|t| t.post.id == post_id) {
+                    thumbnail.post.tags = tags;
+                }
+            }
         };
 
         Ok(())
@@ -151,13 +271,13 @@ impl eframe::App for App {
                 ui.menu_button("File", |ui| {
                     if ui.button("Open File...").clicked() {
                         if let Some(paths) = rfd::FileDialog::new().pick_files() {
-                            self.tx.send(FromGUI::RequestPickedNewPosts(paths)).unwrap();
+                            self.import.pending = Some(PendingImport::Picked(paths));
                         }
                         ui.close_menu();
                     }
                     if ui.button("Open Folder...").clicked() {
                         if let Some(paths) = rfd::FileDialog::new().pick_folders() {
-                            self.tx.send(FromGUI::RequestPickedNewPosts(paths)).unwrap();
+                            self.import.pending = Some(PendingImport::Picked(paths));
                         }
                         ui.close_menu();
                     }
@@ -172,6 +292,16 @@ impl eframe::App for App {
                         self.tx.send(FromGUI::RequestAllPosts).unwrap();
                         ui.close_menu();
                     }
+                    if ui.button("Find Duplicates...").clicked() {
+                        let threshold = maintenance::DUPLICATE_THRESHOLD;
+                        self.tx.send(FromGUI::FindDuplicates(threshold)).unwrap();
+                        self.duplicates = Some(Duplicates {
+                            groups: vec![],
+                            current: 0,
+                            threshold,
+                        });
+                        ui.close_menu();
+                    }
 
                     ui.separator();
                     if ui.button("Toggle Full Sceeen (F11)").clicked() {
@@ -204,27 +334,63 @@ impl eframe::App for App {
                 ui.set_width(ui.available_width());
                 self.settings.main_panel_width = self.settings.window_size.0 - ui.available_width();
 
-                let columns = max((ui.available_width() / THUMBNAIL_SIZE).floor() as _, 1);
-                let rows = (self.posts.len() + columns - 1) / columns;
+                // The viewer takes the place of the grid, keeping the tags beside it.
+                if let Some(viewer) = &mut self.viewer {
+                    if let Some(action) = viewer.ui(ui) {
+                        self.viewer_action(ctx, action);
+                    }
+                    return;
+                }
+
+                let zoom_label = format!("{}px", self.thumbnail_size());
+                ui.add(
+                    egui::Slider::new(&mut self.settings.zoom, 0..=self.thumbnail_sizes.len() - 1)
+                        .show_value(false)
+                        .text(zoom_label),
+                );
+
+                let pixels = self.thumbnail_size();
+                let size = pixels as f32;
+                let columns = max((ui.available_width() / size).floor() as _, 1);
+                let rows = self.posts.len().div_ceil(columns);
+                let mut near_end = false;
+                let mut opened = None;
 
                 egui::ScrollArea::vertical()
                     .drag_to_scroll(false)
-                    .show_rows(ui, THUMBNAIL_SIZE, rows, |ui, row_range| {
+                    .show_rows(ui, size, rows, |ui, row_range| {
                         ui.set_width(ui.available_width());
+                        near_end = row_range.end + 2 >= rows;
 
                         for y in row_range {
                             ui.horizontal(|ui| {
                                 for x in 0..columns {
-                                    let n = (self.posts.len() as i64 - (columns * y + x + 1) as i64)
-                                        as usize;
+                                    let n = columns * y + x;
 
                                     if let Some(thumbnail) = self.posts.get_mut(n) {
-                                        thumbnail.ui(ui, &self.config, n, self.tx.clone());
+                                        if thumbnail.ui(
+                                            ui,
+                                            &self.config,
+                                            n,
+                                            pixels,
+                                            self.tx.clone(),
+                                        ) {
+                                            opened = Some(n);
+                                        }
                                     }
                                 }
                             });
                         }
                     });
+
+                if let Some(index) = opened {
+                    self.open_post(ctx, index);
+                }
+
+                if near_end && self.more_posts && !self.requested_more {
+                    self.requested_more = true;
+                    self.tx.send(FromGUI::RequestMorePosts).unwrap();
+                }
             });
 
         egui::CentralPanel::default().show(ctx, |ui| {
@@ -239,6 +405,16 @@ impl eframe::App for App {
                     );
                     ui.add_space(10.0);
                     editing_tags = editor.has_focus();
+                    if autocomplete(
+                        ui,
+                        &editor,
+                        tag_str,
+                        CompletionField::Editor,
+                        &mut self.completions,
+                        &self.tx,
+                    ) {
+                        self.focus_editor = true;
+                    }
                     if self.focus_editor {
                         editor.request_focus();
                         self.focus_editor = false;
@@ -253,14 +429,28 @@ impl eframe::App for App {
                     .show(ui)
                     .response;
 
+                if autocomplete(
+                    ui,
+                    &search_bar,
+                    &mut self.search,
+                    CompletionField::Search,
+                    &mut self.completions,
+                    &self.tx,
+                ) {
+                    self.focus_search = true;
+                }
+
+                let importing = self.import.pending.is_some();
                 ui.input(|i| {
-                    if i.key_pressed(Key::I) && !search_bar.has_focus() && !editing_tags {
+                    let typing = search_bar.has_focus() || editing_tags || importing;
+                    if i.key_pressed(Key::I) && !typing {
                         self.focus_search = true;
                     }
 
                     if i.key_pressed(Key::Enter) && search_bar.lost_focus() {
                         println!("Search: {}", self.search);
                         if self.search.is_empty() {
+                            self.search_error = None;
                             self.tx.send(FromGUI::RequestAllPosts).unwrap();
                         } else {
                             self.tx.send(FromGUI::Search(self.search.clone())).unwrap();
@@ -273,12 +463,19 @@ impl eframe::App for App {
                     self.focus_search = false;
                 }
 
+                if let Some(error) = &self.search_error {
+                    ui.colored_label(
+                        ui.visuals().error_fg_color,
+                        egui::RichText::new(error).monospace(),
+                    );
+                }
+
                 if let Some(index) = self.selected {
                     if let Some(thumbnail) = self.posts.get_mut(index) {
                         let post = &mut thumbnail.post;
                         let mut tags: Vec<String> =
                             post.tags.clone().into_iter().collect::<Vec<String>>();
-                        tags.sort_unstable();
+                        tag::sort(&mut tags, &self.namespaces);
                         ui.input(|i| {
                             if i.key_pressed(Key::E)
                                 && self.tag_editor.is_none()
@@ -319,6 +516,18 @@ impl eframe::App for App {
                             }
                         });
 
+                        egui::Grid::new("post_info")
+                            .num_columns(2)
+                            .striped(true)
+                            .show(ui, |ui| {
+                                for (label, value) in post_info(post) {
+                                    ui.strong(label);
+                                    ui.label(value);
+                                    ui.end_row();
+                                }
+                            });
+                        ui.separator();
+
                         ui.set_width(ui.available_width());
                         TableBuilder::new(ui)
                             .max_scroll_height(f32::MAX)
@@ -331,14 +540,37 @@ impl eframe::App for App {
                                 });
                             })
                             .body(|mut body| {
+                                let mut group = None;
                                 for tag in tags {
+                                    let namespace = tag::namespace(&tag).to_string();
+                                    if group.as_ref() != Some(&namespace) {
+                                        body.row(20.0, |mut row| {
+                                            row.col(|ui| {
+                                                ui.strong(match namespace.as_str() {
+                                                    "" => "general",
+                                                    namespace => namespace,
+                                                });
+                                            });
+                                        });
+                                    }
+
+                                    let color = namespace_color(&namespace, &self.namespaces);
+                                    group = Some(namespace);
                                     body.row(18.0, |mut row| {
                                         row.col(|ui| {
-                                            ui.label(tag.as_str());
+                                            match color {
+                                                Some(color) => ui.colored_label(color, &tag),
+                                                None => ui.label(&tag),
+                                            };
                                         })
                                         .1
                                         .context_menu(
                                             |ui| {
+                                                if ui.button("Rename or Merge...").clicked() {
+                                                    self.renaming =
+                                                        Some((tag.clone(), tag.clone()));
+                                                    ui.close_menu();
+                                                }
                                                 if ui.button("Remove").clicked() {
                                                     println!(
                                                         "Removed tag {} from #{}",
@@ -359,12 +591,16 @@ impl eframe::App for App {
                 }
             });
         });
+        self.rename_window(ctx);
+        self.duplicates_window(ctx);
+        self.import_window(ctx);
+        self.error_window(ctx);
+        self.warning_window(ctx);
+
         ctx.input(|i| {
             if !i.raw.dropped_files.is_empty() {
                 let files = i.raw.dropped_files.clone();
-                self.tx
-                    .send(FromGUI::RequestDroppedNewPosts(files))
-                    .unwrap();
+                self.import.pending = Some(PendingImport::Dropped(files));
             }
 
             if i.key_pressed(Key::F11) {
@@ -391,7 +627,313 @@ impl App {
     }
 
     fn default_panel_width(&self) -> RangeInclusive<f32> {
-        THUMBNAIL_SIZE..=self.settings.window_size.0 - 200.0
+        self.thumbnail_size() as f32..=self.settings.window_size.0 - 200.0
+    }
+
+    /// The stored thumbnail size the grid is zoomed to.
+    fn thumbnail_size(&self) -> u32 {
+        self.thumbnail_sizes[self.settings.zoom]
+    }
+
+    /// Opens images in the viewer and anything else externally.
+    fn open_post(&mut self, ctx: &Context, index: usize) {
+        let Some(thumbnail) = self.posts.get(index) else {
+            return;
+        };
+
+        match viewer::can_view(&thumbnail.post) {
+            true => self.view_post(ctx, index),
+            false => self.open_externally(index),
+        }
+    }
+
+    fn view_post(&mut self, ctx: &Context, index: usize) {
+        let Some(thumbnail) = self.posts.get(index) else {
+            return;
+        };
+
+        self.viewer = Some(Viewer::open(ctx, index, &thumbnail.post, &self.config));
+        self.tx.send(FromGUI::SetSelected(Some(index))).unwrap();
+
+        if index + 2 >= self.posts.len() && self.more_posts && !self.requested_more {
+            self.requested_more = true;
+            self.tx.send(FromGUI::RequestMorePosts).unwrap();
+        }
+    }
+
+    fn open_externally(&self, index: usize) {
+        let Some(thumbnail) = self.posts.get(index) else {
+            return;
+        };
+
+        let file = &thumbnail.post.get_db_file(&self.config);
+        if let Err(e) = opener::open(file) {
+            eprintln!("Failed to open {:?}\n{:#?}", file, e);
+        }
+    }
+
+    fn viewer_action(&mut self, ctx: &Context, action: Action) {
+        let Some(index) = self.viewer.as_ref().map(|v| v.index) else {
+            return;
+        };
+
+        match action {
+            Action::Close => self.viewer = None,
+            Action::Previous if index > 0 => self.view_post(ctx, index - 1),
+            Action::Previous => {}
+            Action::Next => self.view_post(ctx, index + 1),
+            Action::OpenExternally => self.open_externally(index),
+        }
+    }
+
+    fn duplicates_window(&mut self, ctx: &Context) {
+        let Some(duplicates) = &mut self.duplicates else {
+            return;
+        };
+
+        let mut open = true;
+        let mut keep = None;
+        egui::Window::new("Duplicates")
+            .open(&mut open)
+            .default_size([720.0, 480.0])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Threshold");
+                    ui.add(egui::DragValue::new(&mut duplicates.threshold).clamp_range(0..=32));
+                    if ui.button("Search").clicked() {
+                        let threshold = duplicates.threshold;
+                        self.tx.send(FromGUI::FindDuplicates(threshold)).unwrap();
+                    }
+                    ui.separator();
+
+                    let count = duplicates.groups.len();
+                    if ui.button("⬅").clicked() {
+                        duplicates.current = duplicates.current.saturating_sub(1);
+                    }
+                    if ui.button("➡").clicked() && duplicates.current + 1 < count {
+                        duplicates.current += 1;
+                    }
+                    match count {
+                        0 => ui.label("No duplicates found"),
+                        _ => ui.label(format!("Group {} of {}", duplicates.current + 1, count)),
+                    };
+                });
+                ui.separator();
+
+                let Some(group) = duplicates.groups.get_mut(duplicates.current) else {
+                    return;
+                };
+
+                let width = ui.available_width() / group.len() as f32 - 8.0;
+                let largest = *self.thumbnail_sizes.last().unwrap();
+                ui.horizontal_top(|ui| {
+                    for (i, thumbnail) in group.iter_mut().enumerate() {
+                        ui.vertical(|ui| {
+                            ui.set_width(width);
+                            match thumbnail.texture(ui.ctx(), &self.config, largest) {
+                                None => {
+                                    ui.spinner();
+                                }
+                                Some(None) => {
+                                    ui.label("No thumbnail");
+                                }
+                                Some(Some(texture)) => {
+                                    let size = texture.size_vec2();
+                                    ui.image(texture, size * (width / size.x).min(2.0));
+                                }
+                            }
+
+                            egui::Grid::new(("duplicate_info", i))
+                                .num_columns(2)
+                                .show(ui, |ui| {
+                                    for (label, value) in post_info(&thumbnail.post) {
+                                        ui.strong(label);
+                                        ui.label(value);
+                                        ui.end_row();
+                                    }
+                                    ui.strong("Tags");
+                                    ui.label(thumbnail.post.tags.len().to_string());
+                                    ui.end_row();
+                                });
+
+                            if ui
+                                .button("Keep This")
+                                .on_hover_text(
+                                    "Merge the other posts' tags onto this one and delete them",
+                                )
+                                .clicked()
+                            {
+                                keep = Some(i);
+                            }
+                        });
+                    }
+                });
+            });
+
+        if let Some(i) = keep {
+            let group = duplicates.groups.remove(duplicates.current);
+            let keep = group[i].post.id;
+            let drop = group
+                .iter()
+                .map(|t| t.post.id)
+                .filter(|id| *id != keep)
+                .collect();
+            self.tx.send(FromGUI::MergeDuplicates(keep, drop)).unwrap();
+
+            let last = duplicates.groups.len().saturating_sub(1);
+            duplicates.current = duplicates.current.min(last);
+        }
+
+        if !open {
+            self.duplicates = None;
+        }
+    }
+
+    fn rename_window(&mut self, ctx: &Context) {
+        let Some((old, new)) = &mut self.renaming else {
+            return;
+        };
+
+        let mut open = true;
+        let mut submit = false;
+        egui::Window::new("Rename Tag")
+            .collapsible(false)
+            .resizable(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.label(format!("Rename '{}' to", old));
+                let editor = ui.text_edit_singleline(new);
+                ui.small("Renaming to an existing tag merges the two.");
+                submit = ui.button("Rename").clicked()
+                    || (editor.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)));
+            });
+
+        if submit {
+            let new: String = new.chars().filter(|c| !c.is_whitespace()).collect();
+            if !new.is_empty() && &new != old {
+                self.tx.send(FromGUI::RenameTag(old.clone(), new)).unwrap();
+            }
+            open = false;
+        }
+
+        if !open {
+            self.renaming = None;
+        }
+    }
+
+    fn import_window(&mut self, ctx: &Context) {
+        let Some(pending) = &self.import.pending else {
+            return;
+        };
+        let count = pending.paths().len();
+        let has_folders = pending.paths().iter().any(|path| path.is_dir());
+
+        let import = &mut self.import;
+        let mut open = true;
+        let mut submit = false;
+        let mut cancel = false;
+        egui::Window::new("Import")
+            .collapsible(false)
+            .resizable(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.label(match count {
+                    1 => "Importing 1 item".to_string(),
+                    _ => format!("Importing {} items", count),
+                });
+
+                let tags = egui::TextEdit::singleline(&mut import.tags)
+                    .hint_text("Tags for every post")
+                    .desired_width(f32::INFINITY)
+                    .show(ui)
+                    .response;
+                if autocomplete(
+                    ui,
+                    &tags,
+                    &mut import.tags,
+                    CompletionField::Import,
+                    &mut self.completions,
+                    &self.tx,
+                ) {
+                    tags.request_focus();
+                }
+                ui.separator();
+
+                ui.add_enabled_ui(has_folders, |ui| {
+                    ui.checkbox(&mut import.folder_tags, "Tag with folder names");
+                    ui.add_enabled_ui(import.folder_tags, |ui| {
+                        egui::Grid::new("folder_tags")
+                            .num_columns(2)
+                            .show(ui, |ui| {
+                                ui.label("Namespace");
+                                ui.text_edit_singleline(&mut import.namespace)
+                                    .on_hover_text("Leave empty for general tags");
+                                ui.end_row();
+
+                                ui.label("Depth");
+                                ui.add(egui::DragValue::new(&mut import.depth).clamp_range(0..=32))
+                                    .on_hover_text(
+                                        "Folders to use from the imported one, 0 for all",
+                                    );
+                                ui.end_row();
+                            });
+                    });
+                });
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    submit = ui.button("Import").clicked();
+                    cancel = ui.button("Cancel").clicked();
+                });
+            });
+
+        if !open || cancel {
+            self.import.pending = None;
+        }
+        if !submit {
+            return;
+        }
+
+        let options = import::Options {
+            tags: self
+                .import
+                .tags
+                .split_whitespace()
+                .map(String::from)
+                .collect(),
+            folder_tags: (has_folders && self.import.folder_tags).then(|| FolderTags {
+                namespace: Some(self.import.namespace.trim().to_string()).filter(|n| !n.is_empty()),
+                depth: Some(self.import.depth).filter(|depth| *depth > 0),
+            }),
+        };
+        let message = match self.import.pending.take() {
+            Some(PendingImport::Dropped(files)) => FromGUI::RequestDroppedNewPosts(files, options),
+            Some(PendingImport::Picked(paths)) => FromGUI::RequestPickedNewPosts(paths, options),
+            None => return,
+        };
+        self.tx.send(message).unwrap();
+    }
+
+    fn error_window(&mut self, ctx: &Context) {
+        let Some(error) = &self.error else {
+            return;
+        };
+
+        let color = ctx.style().visuals.error_fg_color;
+        if notice_window(ctx, "Error", color, error) {
+            self.error = None;
+        }
+    }
+
+    fn warning_window(&mut self, ctx: &Context) {
+        if self.warnings.is_empty() {
+            return;
+        }
+
+        let color = ctx.style().visuals.warn_fg_color;
+        if notice_window(ctx, "Warning", color, &self.warnings.join("\n")) {
+            self.warnings.clear();
+        }
     }
 
     fn toggle_fullscreen(&mut self, frame: &mut eframe::Frame) {
@@ -400,9 +942,147 @@ impl App {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompletionField {
+    Search,
+    Editor,
+    Import,
+}
+
+/// Tag suggestions for the text field being typed in.
+#[derive(Default)]
+struct Completions {
+    field: Option<CompletionField>,
+    prefix: String,
+    tags: Vec<TagCount>,
+    open: bool,
+}
+
+/// Suggests tags for the last word of `text` in a popup, replacing the word
+/// with the picked tag. Returns true if a tag was picked.
+fn autocomplete(
+    ui: &mut egui::Ui,
+    response: &egui::Response,
+    text: &mut String,
+    field: CompletionField,
+    completions: &mut Completions,
+    tx: &Sender<FromGUI>,
+) -> bool {
+    let popup_id = response.id.with("completions");
+
+    if response.changed() {
+        completions.field = Some(field);
+        completions.prefix = current_word(text).to_string();
+        completions.tags.clear();
+        ui.memory_mut(|m| m.close_popup());
+        if !completions.prefix.is_empty() {
+            tx.send(FromGUI::CompleteTag(completions.prefix.clone()))
+                .unwrap();
+        }
+    }
+
+    if completions.field != Some(field) {
+        return false;
+    }
+
+    if completions.open {
+        completions.open = false;
+        if response.has_focus() && !completions.tags.is_empty() {
+            ui.memory_mut(|m| m.open_popup(popup_id));
+        }
+    }
+
+    // The tag editor sits at the bottom of the window.
+    let above_or_below = match field {
+        CompletionField::Search | CompletionField::Import => AboveOrBelow::Below,
+        CompletionField::Editor => AboveOrBelow::Above,
+    };
+
+    let mut picked = None;
+    egui::popup_above_or_below_widget(ui, popup_id, response, above_or_below, |ui| {
+        for tag in &completions.tags {
+            let label = format!("{} ({})", tag.name, tag.count);
+            if ui.selectable_label(false, label).clicked() {
+                picked = Some(tag.name.clone());
+            }
+        }
+    });
+
+    let Some(tag) = picked else {
+        return false;
+    };
+
+    let start = text.len() - current_word(text).len();
+    text.truncate(start);
+    text.push_str(&tag);
+    text.push(' ');
+    completions.tags.clear();
+    ui.memory_mut(|m| m.close_popup());
+
+    if let Some(mut state) = TextEditState::load(ui.ctx(), response.id) {
+        let end = CCursor::new(text.chars().count());
+        state.set_ccursor_range(Some(CCursorRange::one(end)));
+        state.store(ui.ctx(), response.id);
+    }
+    true
+}
+
+/// The tag being typed at the end of `text`, without search operators.
+fn current_word(text: &str) -> &str {
+    let word = text.rsplit(char::is_whitespace).next().unwrap_or_default();
+    word.trim_start_matches(['-', '('])
+}
+
+fn post_info(post: &Post) -> Vec<(&'static str, String)> {
+    let mut info = vec![
+        ("Post", format!("#{}", post.id)),
+        ("Name", post.original_name.clone()),
+    ];
+
+    if let Some(size) = post.file_size {
+        info.push(("Size", post::format_bytes(size as u64)));
+    }
+    if let Some(dimensions) = post.dimensions() {
+        info.push(("Dimensions", dimensions));
+    }
+    if let Some(mime) = &post.mime {
+        info.push(("Type", mime.clone()));
+    }
+    if let Some(duration) = post.duration {
+        info.push(("Duration", format!("{:.1}s", duration)));
+    }
+    if let Some(codec) = &post.codec {
+        info.push(("Codec", codec.clone()));
+    }
+    info
+}
+
+/// Shows `text` in a window with an OK button. Returns whether it was closed.
+fn notice_window(ctx: &Context, title: &str, color: egui::Color32, text: &str) -> bool {
+    let mut open = true;
+    let mut dismiss = false;
+    egui::Window::new(title)
+        .collapsible(false)
+        .resizable(false)
+        .open(&mut open)
+        .show(ctx, |ui| {
+            ui.colored_label(color, text);
+            dismiss = ui.button("OK").clicked();
+        });
+
+    !open || dismiss
+}
+
+fn namespace_color(namespace: &str, namespaces: &[Namespace]) -> Option<egui::Color32> {
+    let color = namespaces.iter().find(|n| n.name == namespace)?.color?;
+    let [_, r, g, b] = color.to_be_bytes();
+    Some(egui::Color32::from_rgb(r, g, b))
+}
+
 pub struct PostThumbnail {
     post: Post,
-    texture: Option<Promise<Option<egui::TextureHandle>>>,
+    /// The size loaded, and the texture once it's ready.
+    texture: Option<(u32, Promise<Option<egui::TextureHandle>>)>,
 }
 
 impl From<Post> for PostThumbnail {
@@ -418,47 +1098,74 @@ impl PostThumbnail {
     fn load_thumbnail(ctx: egui::Context, path: PathBuf) -> Option<egui::TextureHandle> {
         let image = image::io::Reader::open(path).ok()?.decode().ok()?;
         let size = [image.width() as _, image.height() as _];
-        let image = egui::ColorImage::from_rgb(size, image.to_rgb8().as_flat_samples().as_slice());
+        let image = egui::ColorImage::from_rgba_unmultiplied(
+            size,
+            image.to_rgba8().as_flat_samples().as_slice(),
+        );
         Some(ctx.load_texture("thumbnail", image, Default::default()))
     }
 
-    fn ui(&mut self, ui: &mut egui::Ui, config: &Config, index: usize, tx: Sender<FromGUI>) {
-        let has_thumbnail = self.texture.get_or_insert_with(|| {
-            let ctx = ui.ctx().clone();
-            let path = self.post.get_db_thumbnail(config);
-            Promise::spawn_thread("load_thumbnail", move || Self::load_thumbnail(ctx, path))
+    /// The thumbnail texture of `size`, loaded in the background the first
+    /// time it's asked for. `None` while it's loading.
+    fn texture(
+        &mut self,
+        ctx: &egui::Context,
+        config: &Config,
+        size: u32,
+    ) -> Option<&Option<egui::TextureHandle>> {
+        if self
+            .texture
+            .as_ref()
+            .is_some_and(|(loaded, _)| *loaded != size)
+        {
+            self.texture = None;
+        }
+
+        let (_, texture) = self.texture.get_or_insert_with(|| {
+            let ctx = ctx.clone();
+            let path = self.post.get_db_thumbnail(config, size);
+            // Posts from before a size was configured only have the default
+            // one until `pkrs thumbnails regen`.
+            let fallback = self.post.get_db_thumbnail(config, config.thumbnail_size);
+            let texture = Promise::spawn_thread("load_thumbnail", move || {
+                Self::load_thumbnail(ctx.clone(), path)
+                    .or_else(|| Self::load_thumbnail(ctx, fallback))
+            });
+            (size, texture)
         });
+        texture.ready()
+    }
 
-        match has_thumbnail.ready() {
+    fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        config: &Config,
+        index: usize,
+        size: u32,
+        tx: Sender<FromGUI>,
+    ) -> bool {
+        let cell = egui::vec2(size as f32, size as f32);
+        let info = format!("#{} {}", &self.post.id, &self.post.original_name);
+        match self.texture(ui.ctx(), config, size) {
             None => {
-                ui.add_sized(THUMBNAIL_VEC2, egui::Spinner::new());
+                ui.add_sized(cell, egui::Spinner::new());
+                false
             }
             Some(thumbnail) => {
-                let info = format!("#{} {}", &self.post.id, &self.post.original_name);
                 let button = match thumbnail {
-                    None => ui.add_sized(
-                        THUMBNAIL_VEC2,
-                        egui::Button::new(info).frame(false).wrap(true),
-                    ),
+                    None => ui.add_sized(cell, egui::Button::new(info).frame(false).wrap(true)),
                     Some(texture) => {
-                        let size = texture.size_vec2();
-                        ui.add_sized(
-                            THUMBNAIL_VEC2,
-                            egui::ImageButton::new(texture, size).frame(false),
-                        )
-                        .on_hover_text_at_pointer(info)
+                        // Only a fallback is bigger than the cell.
+                        let scale = (cell.x / texture.size_vec2().max_elem()).min(1.0);
+                        let size = texture.size_vec2() * scale;
+                        ui.add_sized(cell, egui::ImageButton::new(texture, size).frame(false))
+                            .on_hover_text_at_pointer(info)
                     }
                 };
-                if button.double_clicked() {
-                    let file = &self.post.get_db_file(config);
-                    if let Err(e) = opener::open(file) {
-                        eprintln!("Failed to open {:?}\n{:#?}", file, e);
-                    }
-                }
-
                 if button.clicked() {
                     tx.send(FromGUI::SetSelected(Some(index))).unwrap();
                 }
+                button.double_clicked()
             }
         }
     }
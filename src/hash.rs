@@ -1,4 +1,7 @@
 use blake3::{Hash, Hasher};
+use image::imageops::FilterType;
+use image::io::Reader as ImageReader;
+use image::DynamicImage;
 use std::{error::Error, fs, io, path::Path};
 
 pub fn hash_file_blake3(path: &Path) -> Result<Hash, Box<dyn Error>> {
@@ -7,3 +10,28 @@ pub fn hash_file_blake3(path: &Path) -> Result<Hash, Box<dyn Error>> {
     io::copy(&mut file, &mut hasher)?;
     Ok(hasher.finalize())
 }
+
+/// A 64-bit difference hash, which stays close for resized or re-encoded
+/// copies of an image. Compare two with `hamming`.
+pub fn dhash(image: &DynamicImage) -> u64 {
+    let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | brighter as u64;
+        }
+    }
+    hash
+}
+
+pub fn dhash_file(path: &Path) -> Result<u64, Box<dyn Error>> {
+    let image = ImageReader::open(path)?.with_guessed_format()?.decode()?;
+    Ok(dhash(&image))
+}
+
+/// How many bits differ between two hashes.
+pub fn hamming(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
@@ -0,0 +1,177 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use walkdir::WalkDir;
+
+use crate::{db::Database, post::Post, sidecar, tag};
+
+/// A file to import, with the folders between it and the folder it was
+/// found in.
+pub struct File {
+    pub path: PathBuf,
+    /// From the imported folder down, empty for files imported on their own.
+    pub folders: Vec<String>,
+}
+
+impl AsRef<Path> for File {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// How imported files are tagged, besides their sidecars.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Added to every imported post.
+    pub tags: Vec<String>,
+    pub folder_tags: Option<FolderTags>,
+}
+
+/// Turns the folders a file was found in into tags.
+#[derive(Debug, Clone, Default)]
+pub struct FolderTags {
+    /// Put in front of each folder name, as `namespace:folder`.
+    pub namespace: Option<String>,
+    /// How many folders to use, counting from the imported one.
+    pub depth: Option<usize>,
+}
+
+impl FolderTags {
+    pub fn tags(&self, file: &File) -> Vec<String> {
+        let namespace = self.namespace.as_deref().unwrap_or_default();
+        file.folders
+            .iter()
+            .take(self.depth.unwrap_or(usize::MAX))
+            .filter_map(|folder| tag::clean(namespace, folder))
+            .collect()
+    }
+}
+
+/// Lists `paths`, with the files inside any folders.
+pub fn files(paths: Vec<PathBuf>) -> Vec<File> {
+    let mut files = Vec::new();
+
+    for path in paths {
+        if !path.is_dir() {
+            files.push(File {
+                path,
+                folders: Vec::new(),
+            });
+            continue;
+        }
+
+        // The imported folder's own name is the first tag, even when it was
+        // given as `.` or `..`.
+        let name = fs::canonicalize(&path)
+            .ok()
+            .and_then(|path| Some(path.file_name()?.to_string_lossy().to_string()));
+
+        for entry in WalkDir::new(&path).into_iter().filter_map(|e| e.ok()) {
+            let file = entry.path();
+            if !file.is_file() {
+                continue;
+            }
+
+            let below = file
+                .parent()
+                .and_then(|parent| parent.strip_prefix(&path).ok())
+                .into_iter()
+                .flat_map(|folders| folders.components())
+                .filter_map(|folder| match folder {
+                    Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+                    _ => None,
+                });
+            let folders = name.clone().into_iter().chain(below).collect();
+
+            files.push(File {
+                path: file.to_path_buf(),
+                folders,
+            });
+        }
+    }
+    files
+}
+
+/// Adds `file` as a post, tagged from `options` and its sidecars. Returns
+/// the post and the tags it was given.
+pub fn post(
+    file: &File,
+    options: &Options,
+    db: &mut Database,
+) -> Result<(Post, Vec<String>), Box<dyn Error>> {
+    let mut post = Post::new(&file.path, db)?;
+
+    let mut tags = options.tags.clone();
+    tags.extend(sidecar::read_tags(&file.path, &db.config));
+    if let Some(folder_tags) = &options.folder_tags {
+        tags.extend(folder_tags.tags(file));
+    }
+    post.add_tags(&tags, db)?;
+
+    Ok((post, tags))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(folders: &[&str]) -> File {
+        File {
+            path: PathBuf::from("image.png"),
+            folders: folders.iter().map(|folder| folder.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn folder_tags() {
+        let file = file(&["animals", "big cats", "lions"]);
+        let tags = |namespace: Option<&str>, depth: Option<usize>| {
+            let folder_tags = FolderTags {
+                namespace: namespace.map(String::from),
+                depth,
+            };
+            folder_tags.tags(&file)
+        };
+
+        assert_eq!(tags(None, None), ["animals", "big_cats", "lions"]);
+        assert_eq!(tags(None, Some(2)), ["animals", "big_cats"]);
+        assert_eq!(tags(None, Some(0)), Vec::<String>::new());
+        assert_eq!(tags(Some("album"), Some(1)), ["album:animals"]);
+    }
+
+    #[test]
+    fn files_lists_the_folders_below_the_imported_one() {
+        let root = std::env::temp_dir().join(format!("pkrs-import-{}", std::process::id()));
+        let dogs = root.join("dogs");
+        fs::create_dir_all(dogs.join("puppies")).unwrap();
+        fs::write(dogs.join("a.png"), "").unwrap();
+        fs::write(dogs.join("puppies").join("b.png"), "").unwrap();
+
+        let folders = |path: PathBuf| {
+            let mut files: Vec<_> = files(vec![path])
+                .into_iter()
+                .map(|file| (file.path.file_name().unwrap().to_owned(), file.folders))
+                .collect();
+            files.sort();
+            files
+        };
+        let expected = vec![
+            ("a.png".into(), vec!["dogs".to_string()]),
+            (
+                "b.png".into(),
+                vec!["dogs".to_string(), "puppies".to_string()],
+            ),
+        ];
+
+        assert_eq!(folders(dogs.clone()), expected);
+        // `.` and `..` name the folder they lead to, and aren't tags.
+        assert_eq!(folders(dogs.join(".")), expected);
+        assert_eq!(folders(dogs.join("puppies").join("..")), expected);
+        assert_eq!(folders(dogs.join("a.png")), [("a.png".into(), vec![])]);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
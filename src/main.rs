@@ -4,10 +4,18 @@ mod config;
 mod db;
 mod gui;
 mod hash;
+mod import;
+mod maintenance;
+mod media;
 mod message;
+mod metatag;
 mod post;
 mod search;
+mod sidecar;
+mod tag;
 mod thumbnail;
+mod video;
+mod viewer;
 mod worker;
 use cli::Cli;
 use config::Config;
@@ -0,0 +1,251 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use walkdir::WalkDir;
+
+use crate::{config::Config, db::Database, hash, media, post::Post, search::Query, thumbnail};
+
+/// Files and thumbnails in the database folders that no post points to.
+pub fn orphan_files(db: &Database) -> Result<Vec<(PathBuf, u64)>, Box<dyn Error>> {
+    let mut expected = HashSet::new();
+    for post in Query::default().run(&Default::default(), db)? {
+        expected.insert(post.get_db_file(&db.config));
+        expected.extend(
+            post.get_db_thumbnails(&db.config)
+                .into_iter()
+                .map(|(_, path)| path),
+        );
+    }
+
+    let mut orphans = unexpected_files(&db.config.db_file_path, &expected)?;
+    orphans.extend(unexpected_files(&db.config.db_thumbnail_path, &expected)?);
+    Ok(orphans)
+}
+
+fn unexpected_files(
+    folder: &str,
+    expected: &HashSet<PathBuf>,
+) -> Result<Vec<(PathBuf, u64)>, Box<dyn Error>> {
+    // Only look where posts are stored (`ab/cd/<hash>`), so nothing else
+    // sharing the folder is touched.
+    let entries = WalkDir::new(folder)
+        .min_depth(3)
+        .max_depth(3)
+        .into_iter()
+        .filter_map(|e| e.ok());
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry.into_path();
+        if path.is_file() && !expected.contains(&path) {
+            let size = fs::metadata(&path)?.len();
+            files.push((path, size));
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Removes `path` along with the hash folders above it once they're empty.
+pub fn remove_stored_file(path: &Path) -> Result<(), Box<dyn Error>> {
+    fs::remove_file(path)?;
+
+    for folder in path.ancestors().skip(1).take(2) {
+        if fs::remove_dir(folder).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+pub enum Problem {
+    Missing,
+    Corrupt(blake3::Hash),
+    Unreadable(String),
+    Unexpected,
+}
+
+/// Re-hashes the files of `posts` and returns the ones that no longer match.
+/// With `all_posts`, files that belong to no post are reported too.
+pub fn verify(
+    posts: &[Post],
+    all_posts: bool,
+    jobs: usize,
+    config: &Config,
+) -> Result<Vec<(PathBuf, Problem)>, Box<dyn Error>> {
+    let checked = parallel(posts, jobs, |post| {
+        let path = post.get_db_file(config);
+        if !path.is_file() {
+            return Some((path, Problem::Missing));
+        }
+
+        match hash::hash_file_blake3(&path) {
+            Ok(hash) if hash == post.get_hash() => None,
+            Ok(hash) => Some((path, Problem::Corrupt(hash))),
+            Err(e) => Some((path, Problem::Unreadable(e.to_string()))),
+        }
+    });
+
+    let mut problems: Vec<_> = checked.into_iter().flatten().collect();
+
+    if all_posts {
+        let expected = posts.iter().map(|p| p.get_db_file(config)).collect();
+        for (path, _) in unexpected_files(&config.db_file_path, &expected)? {
+            problems.push((path, Problem::Unexpected));
+        }
+    }
+    Ok(problems)
+}
+
+/// Rebuilds the thumbnails of `posts`, printing each one as it finishes.
+/// Returns the posts that failed and why.
+pub fn regen_thumbnails(posts: &[Post], jobs: usize, config: &Config) -> Vec<(i64, String)> {
+    let done = AtomicUsize::new(0);
+
+    let results = parallel(posts, jobs, |post| {
+        let thumbnails = post.get_db_thumbnails(config);
+        let result = thumbnail::create(&post.get_db_file(config), &thumbnails, config)
+            .map_err(|e| e.to_string());
+
+        let n = done.fetch_add(1, Ordering::Relaxed) + 1;
+        match &result {
+            Ok(()) => println!("[{}/{}] Post #{}", n, posts.len(), post.id),
+            Err(e) => eprintln!("[{}/{}] Post #{} failed: {}", n, posts.len(), post.id, e),
+        }
+        result.err().map(|e| (post.id, e))
+    });
+
+    results.into_iter().flatten().collect()
+}
+
+/// Reads the sizes, dimensions and MIME types of `posts`, imported before
+/// they were recorded. `read` is called as each one is done.
+pub fn backfill_metadata(
+    posts: &[Post],
+    jobs: usize,
+    db: &Database,
+    read: impl Fn(&Post) + Sync,
+) -> Result<(), Box<dyn Error>> {
+    let config = &db.config;
+    let metadata = parallel(posts, jobs, |post| {
+        let path = post.get_db_file(config);
+        let file_size = fs::metadata(&path).ok().map(|file| file.len() as i64);
+        let metadata = match file_size {
+            Some(_) => media::read_info(&path),
+            None => Default::default(),
+        };
+
+        read(post);
+        (file_size, metadata)
+    });
+
+    db.begin()?;
+    for (post, (file_size, metadata)) in posts.iter().zip(metadata) {
+        db.set_metadata(post.id, file_size, &metadata)?;
+    }
+    db.commit()?;
+    Ok(())
+}
+
+/// How many bits perceptual hashes may differ by and still count as duplicates.
+pub const DUPLICATE_THRESHOLD: u32 = 6;
+
+/// Hashes images imported before perceptual hashes were recorded. Returns
+/// how many were hashed.
+pub fn fill_phashes(db: &Database, jobs: usize) -> Result<usize, Box<dyn Error>> {
+    let posts = db.get_unhashed_images()?;
+    let config = &db.config;
+    let hashes = parallel(&posts, jobs, |post| {
+        hash::dhash_file(&post.get_db_file(config)).ok()
+    });
+
+    let mut hashed = 0;
+    db.begin()?;
+    for (post, phash) in posts.iter().zip(hashes) {
+        if let Some(phash) = phash {
+            db.set_phash(post.id, phash as i64)?;
+            hashed += 1;
+        }
+    }
+    db.commit()?;
+    Ok(hashed)
+}
+
+/// Groups posts whose perceptual hashes differ by at most `threshold` bits,
+/// directly or through other posts in the group.
+pub fn find_duplicates(db: &Database, threshold: u32) -> Result<Vec<Vec<Post>>, Box<dyn Error>> {
+    fn root(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+
+    let hashes = db.get_phashes()?;
+    let mut parents: Vec<usize> = (0..hashes.len()).collect();
+    for i in 0..hashes.len() {
+        for j in i + 1..hashes.len() {
+            if hash::hamming(hashes[i].1, hashes[j].1) <= threshold {
+                let (a, b) = (root(&mut parents, i), root(&mut parents, j));
+                parents[b.max(a)] = a.min(b);
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<i64>> = HashMap::new();
+    for (i, (post_id, _)) in hashes.iter().enumerate() {
+        groups
+            .entry(root(&mut parents, i))
+            .or_default()
+            .push(*post_id);
+    }
+
+    let mut groups: Vec<_> = groups.into_values().filter(|g| g.len() > 1).collect();
+    groups.sort();
+
+    let mut duplicates = Vec::new();
+    for group in groups {
+        let posts = group.into_iter().map(|id| db.get_post_id(id));
+        duplicates.push(posts.collect::<Result<Vec<_>, _>>()?);
+    }
+    Ok(duplicates)
+}
+
+/// Maps `items` over `jobs` threads, keeping their order.
+pub fn parallel<T, R, F>(items: &[T], jobs: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(items.len()));
+
+    thread::scope(|s| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(i) else {
+                    break;
+                };
+                let result = f(item);
+                results.lock().unwrap().push((i, result));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
@@ -0,0 +1,98 @@
+use image::io::Reader as ImageReader;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::{hash, video};
+
+/// What's known about the contents of a file.
+#[derive(Debug, Default)]
+pub struct Metadata {
+    pub mime: Option<String>,
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    /// Seconds, for videos.
+    pub duration: Option<f64>,
+    pub codec: Option<String>,
+    /// `hash::dhash` of images, stored as the same bits.
+    pub phash: Option<i64>,
+}
+
+/// Sniffs the MIME type of `path`, reads the dimensions of images and videos
+/// and hashes images. Anything that can't be read is left empty.
+pub fn read(path: &Path) -> Metadata {
+    read_with(path, true)
+}
+
+/// Like `read`, without the hash, so images aren't decoded.
+pub fn read_info(path: &Path) -> Metadata {
+    read_with(path, false)
+}
+
+fn read_with(path: &Path, hash: bool) -> Metadata {
+    let mut metadata = Metadata::default();
+
+    let mime = match sniff_mime(path) {
+        Ok(mime) => mime,
+        Err(e) => {
+            eprintln!("{} {}", e, path.display());
+            return metadata;
+        }
+    };
+    metadata.mime = Some(mime.to_string());
+
+    if mime.starts_with("video/") || video::is_video(path) {
+        match video::probe(path) {
+            Ok(info) => {
+                metadata.width = info.width;
+                metadata.height = info.height;
+                metadata.duration = info.duration;
+                metadata.codec = info.codec;
+            }
+            Err(e) => eprintln!("{} {}", e, path.display()),
+        }
+    } else if mime.starts_with("image/") {
+        let reader = ImageReader::open(path).and_then(|reader| reader.with_guessed_format());
+        let dimensions = match reader {
+            // One decode gives both the dimensions and the hash.
+            Ok(reader) if hash => reader.decode().ok().map(|image| {
+                metadata.phash = Some(hash::dhash(&image) as i64);
+                (image.width(), image.height())
+            }),
+            Ok(reader) => reader.into_dimensions().ok(),
+            Err(_) => None,
+        };
+
+        if let Some((width, height)) = dimensions {
+            metadata.width = Some(width as i64);
+            metadata.height = Some(height as i64);
+        }
+    }
+    metadata
+}
+
+/// Guesses the MIME type from the first bytes of the file.
+pub fn sniff_mime(path: &Path) -> io::Result<&'static str> {
+    let mut header = Vec::with_capacity(64);
+    File::open(path)?.take(64).read_to_end(&mut header)?;
+
+    if let Ok(format) = image::guess_format(&header) {
+        return Ok(format.to_mime_type());
+    }
+
+    let mime = match header.as_slice() {
+        [_, _, _, _, b'f', b't', b'y', b'p', b'q', b't', ..] => "video/quicktime",
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => "video/mp4",
+        [0x1A, 0x45, 0xDF, 0xA3, ..] if header.windows(4).any(|w| w == b"webm") => "video/webm",
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => "video/x-matroska",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'A', b'V', b'I', b' ', ..] => "video/x-msvideo",
+        [0x30, 0x26, 0xB2, 0x75, ..] => "video/x-ms-asf",
+        [b'%', b'P', b'D', b'F', ..] => "application/pdf",
+        // The header may cut a character in half.
+        _ => match std::str::from_utf8(&header) {
+            Err(e) if e.error_len().is_some() => "application/octet-stream",
+            _ => "text/plain",
+        },
+    };
+    Ok(mime)
+}
@@ -1,20 +1,38 @@
-use crate::gui::PostThumbnail;
+use std::collections::HashSet;
+
+use crate::{db::TagCount, gui::PostThumbnail, import, tag::Namespace};
 
 pub enum FromWorker {
     RequestContext,
     SetPosts(Vec<PostThumbnail>),
+    AppendPosts(Vec<PostThumbnail>),
     ShowProgress(bool),
     SetProgress(f32, f32),
     SetProgressMessage(Option<String>),
     SetSelected(Option<usize>),
+    SetSearchError(Option<String>),
+    SetNamespaces(Vec<Namespace>),
+    SetPostTags(i64, HashSet<String>),
+    RenamedTag(String, String),
+    SetCompletions(String, Vec<TagCount>),
+    SetDuplicates(Vec<Vec<PostThumbnail>>),
+    RemovedPosts(Vec<i64>),
+    ShowError(String),
+    ShowWarnings(Vec<String>),
 }
 pub enum FromGUI {
     SendContext(eframe::egui::Context),
     RequestAllPosts,
-    RequestDroppedNewPosts(Vec<eframe::egui::DroppedFile>),
-    RequestPickedNewPosts(Vec<std::path::PathBuf>),
+    RequestMorePosts,
+    RequestDroppedNewPosts(Vec<eframe::egui::DroppedFile>, import::Options),
+    RequestPickedNewPosts(Vec<std::path::PathBuf>, import::Options),
     SetSelected(Option<usize>),
     RemoveTag(i64, String),
     AddTag(i64, String),
+    RenameTag(String, String),
     Search(String),
+    CompleteTag(String),
+    FindDuplicates(u32),
+    /// Merges the tags of the dropped posts onto the kept one, then deletes them.
+    MergeDuplicates(i64, Vec<i64>),
 }
@@ -0,0 +1,451 @@
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::types::Value;
+
+/// A search term that filters on a column of `posts` instead of its tags,
+/// written as `key:value`.
+#[derive(Debug, PartialEq)]
+pub enum Metatag {
+    Id(Comparison),
+    TagCount(Comparison),
+    Extension(String),
+    Name(String),
+    Width(Comparison),
+    Height(Comparison),
+    Ratio(Ratio),
+    /// File size in bytes.
+    Size(Comparison),
+    Mime(String),
+}
+
+pub const KEYS: [&str; 10] = [
+    "id", "tagcount", "ext", "name", "width", "height", "ratio", "size", "mime", "order",
+];
+
+impl Metatag {
+    /// Returns `None` if `key` isn't a metatag, so the term is treated as a tag.
+    pub fn parse(key: &str, value: &str) -> Option<Result<Self, String>> {
+        let metatag = match key {
+            "id" => Comparison::parse(value).map(Metatag::Id),
+            "tagcount" => Comparison::parse(value).map(Metatag::TagCount),
+            "ext" => non_empty(value).map(|v| Metatag::Extension(v.trim_start_matches('.').into())),
+            "name" => non_empty(value).map(|v| Metatag::Name(v.into())),
+            "width" => Comparison::parse(value).map(Metatag::Width),
+            "height" => Comparison::parse(value).map(Metatag::Height),
+            "ratio" => Ratio::parse(value).map(Metatag::Ratio),
+            "size" => Comparison::parse_with(value, parse_bytes).map(Metatag::Size),
+            "mime" => non_empty(value).map(|v| Metatag::Mime(v.into())),
+            _ => return None,
+        };
+        Some(metatag)
+    }
+
+    pub fn to_sql(&self, params: &mut Vec<Value>) -> String {
+        match self {
+            Metatag::Id(cmp) => cmp.to_sql("posts.post_id", params),
+            Metatag::TagCount(cmp) => cmp.to_sql(
+                "(SELECT COUNT(*) FROM taggings WHERE taggings.post_id = posts.post_id)",
+                params,
+            ),
+            Metatag::Extension(ext) => text_sql("posts.extension", ext, params),
+            Metatag::Name(name) => text_sql("posts.original_name", name, params),
+            Metatag::Width(cmp) => cmp.to_sql("posts.width", params),
+            Metatag::Height(cmp) => cmp.to_sql("posts.height", params),
+            Metatag::Ratio(ratio) => ratio.to_sql(params),
+            Metatag::Size(cmp) => cmp.to_sql("posts.file_size", params),
+            Metatag::Mime(mime) => text_sql("posts.mime", mime, params),
+        }
+    }
+}
+
+impl fmt::Display for Metatag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Metatag::Id(cmp) => write!(f, "id:{}", cmp),
+            Metatag::TagCount(cmp) => write!(f, "tagcount:{}", cmp),
+            Metatag::Extension(ext) => write!(f, "ext:{}", ext),
+            Metatag::Name(name) => write!(f, "name:{}", name),
+            Metatag::Width(cmp) => write!(f, "width:{}", cmp),
+            Metatag::Height(cmp) => write!(f, "height:{}", cmp),
+            Metatag::Ratio(ratio) => write!(f, "ratio:{}", ratio),
+            Metatag::Size(cmp) => write!(f, "size:{}", cmp),
+            Metatag::Mime(mime) => write!(f, "mime:{}", mime),
+        }
+    }
+}
+
+fn non_empty(value: &str) -> Result<&str, String> {
+    match value.is_empty() {
+        true => Err("expected a value".to_string()),
+        false => Ok(value),
+    }
+}
+
+/// Case-insensitive match, using GLOB when the value has wildcards.
+fn text_sql(column: &str, value: &str, params: &mut Vec<Value>) -> String {
+    let value = value.to_lowercase();
+    match value.contains(['*', '?']) {
+        true => {
+            params.push(Value::from(value.replace('[', "[[]")));
+            format!("lower({}) GLOB ?", column)
+        }
+        false => {
+            params.push(Value::from(value));
+            format!("lower({}) = ?", column)
+        }
+    }
+}
+
+/// A numeric comparison such as `5`, `>5`, `<=5` or the inclusive range `2..8`.
+#[derive(Debug, PartialEq)]
+pub enum Comparison {
+    Eq(i64),
+    Lt(i64),
+    Le(i64),
+    Gt(i64),
+    Ge(i64),
+    Between(i64, i64),
+}
+
+impl Comparison {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        Self::parse_with(value, |s| {
+            s.parse::<i64>()
+                .map_err(|_| format!("expected a number, found '{}'", s))
+        })
+    }
+
+    /// Parses the numbers with `number`, for values with units.
+    pub fn parse_with(
+        value: &str,
+        number: impl Fn(&str) -> Result<i64, String>,
+    ) -> Result<Self, String> {
+        if let Some((min, max)) = value.split_once("..") {
+            return match (min.is_empty(), max.is_empty()) {
+                (true, true) => Err("expected a number before or after '..'".to_string()),
+                (true, false) => Ok(Comparison::Le(number(max)?)),
+                (false, true) => Ok(Comparison::Ge(number(min)?)),
+                (false, false) => Ok(Comparison::Between(number(min)?, number(max)?)),
+            };
+        }
+
+        if let Some(n) = value.strip_prefix(">=") {
+            Ok(Comparison::Ge(number(n)?))
+        } else if let Some(n) = value.strip_prefix("<=") {
+            Ok(Comparison::Le(number(n)?))
+        } else if let Some(n) = value.strip_prefix('>') {
+            Ok(Comparison::Gt(number(n)?))
+        } else if let Some(n) = value.strip_prefix('<') {
+            Ok(Comparison::Lt(number(n)?))
+        } else {
+            Ok(Comparison::Eq(number(value.trim_start_matches('='))?))
+        }
+    }
+
+    pub fn to_sql(&self, column: &str, params: &mut Vec<Value>) -> String {
+        let (sql, values) = match *self {
+            Comparison::Eq(n) => ("= ?", vec![n]),
+            Comparison::Lt(n) => ("< ?", vec![n]),
+            Comparison::Le(n) => ("<= ?", vec![n]),
+            Comparison::Gt(n) => ("> ?", vec![n]),
+            Comparison::Ge(n) => (">= ?", vec![n]),
+            Comparison::Between(min, max) => ("BETWEEN ? AND ?", vec![min, max]),
+        };
+
+        params.extend(values.into_iter().map(Value::from));
+        format!("{} {}", column, sql)
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Comparison::Eq(n) => write!(f, "{}", n),
+            Comparison::Lt(n) => write!(f, "<{}", n),
+            Comparison::Le(n) => write!(f, "<={}", n),
+            Comparison::Gt(n) => write!(f, ">{}", n),
+            Comparison::Ge(n) => write!(f, ">={}", n),
+            Comparison::Between(min, max) => write!(f, "{}..{}", min, max),
+        }
+    }
+}
+
+/// A size such as `500`, `200kb`, `1.5mb` or `2gb`, in powers of 1024.
+fn parse_bytes(value: &str) -> Result<i64, String> {
+    let value = value.to_lowercase();
+    let number = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit: i64 = match &value[number.len()..] {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        unit => return Err(format!("unknown unit '{}', expected b, kb, mb or gb", unit)),
+    };
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("expected a size, found '{}'", value))?;
+    Ok((number * unit as f64).round() as i64)
+}
+
+/// An aspect ratio written as `16:9` or `1.5`, optionally compared with
+/// `>`, `>=`, `<` or `<=`. Equal ratios match within 1%.
+#[derive(Debug, PartialEq)]
+pub struct Ratio {
+    operator: &'static str,
+    ratio: f64,
+    text: String,
+}
+
+impl Ratio {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (operator, text) = [">=", "<=", ">", "<"]
+            .into_iter()
+            .find_map(|op| value.strip_prefix(op).map(|rest| (op, rest)))
+            .unwrap_or(("=", value));
+
+        let number = |s: &str| {
+            s.parse::<f64>()
+                .map_err(|_| format!("expected a ratio such as 16:9, found '{}'", value))
+        };
+        let ratio = match text.split_once(':') {
+            Some((width, height)) => number(width)? / number(height)?,
+            None => number(text)?,
+        };
+
+        if !ratio.is_finite() || ratio <= 0.0 {
+            return Err(format!("'{}' is not a valid ratio", value));
+        }
+
+        Ok(Ratio {
+            operator,
+            ratio,
+            text: text.to_string(),
+        })
+    }
+
+    pub fn to_sql(&self, params: &mut Vec<Value>) -> String {
+        let column = "(posts.width * 1.0 / posts.height)";
+        match self.operator {
+            "=" => {
+                params.extend([Value::from(self.ratio), Value::from(self.ratio * 0.01)]);
+                format!("abs({} - ?) <= ?", column)
+            }
+            operator => {
+                params.push(Value::from(self.ratio));
+                format!("{} {} ?", column, operator)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Ratio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.operator {
+            "=" => write!(f, "{}", self.text),
+            operator => write!(f, "{}{}", operator, self.text),
+        }
+    }
+}
+
+/// The sort order of search results, set with `order:key` or `order:key_asc`
+/// and `order:key_desc` to pick the direction.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Order {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SortKey {
+    Id,
+    Name,
+    TagCount,
+    Size,
+    /// Shuffled by a seed, so the same seed pages through the same order.
+    Random(i64),
+}
+
+impl Default for Order {
+    fn default() -> Self {
+        Order {
+            key: SortKey::Id,
+            descending: true,
+        }
+    }
+}
+
+impl Order {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (name, direction) = match value.rsplit_once('_') {
+            Some((name, "asc")) => (name, Some(false)),
+            Some((name, "desc")) => (name, Some(true)),
+            _ => (value, None),
+        };
+
+        let key = match name {
+            "id" => SortKey::Id,
+            "name" => SortKey::Name,
+            "tagcount" => SortKey::TagCount,
+            "size" => SortKey::Size,
+            "random" => SortKey::Random(random_seed()),
+            _ => {
+                return Err(format!(
+                    "unknown order '{}', expected id, name, tagcount, size or random",
+                    name
+                ))
+            }
+        };
+
+        let descending = direction.unwrap_or(!matches!(key, SortKey::Name));
+
+        Ok(Order { key, descending })
+    }
+
+    pub fn to_sql(self, params: &mut Vec<Value>) -> String {
+        let column = match self.key {
+            SortKey::Id => "posts.post_id".to_string(),
+            SortKey::Name => "lower(posts.original_name)".to_string(),
+            SortKey::TagCount => {
+                "(SELECT COUNT(*) FROM taggings WHERE taggings.post_id = posts.post_id)".to_string()
+            }
+            SortKey::Size => "posts.file_size".to_string(),
+            SortKey::Random(seed) => {
+                params.push(Value::from(seed));
+                "seeded_random(posts.post_id, ?)".to_string()
+            }
+        };
+
+        let direction = match self.descending {
+            true => "DESC",
+            false => "ASC",
+        };
+
+        // Ties are broken by id so that pages never overlap.
+        format!("{0} {1}, posts.post_id {1}", column, direction)
+    }
+}
+
+impl fmt::Display for Order {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.key {
+            SortKey::Id => "id",
+            SortKey::Name => "name",
+            SortKey::TagCount => "tagcount",
+            SortKey::Size => "size",
+            SortKey::Random(_) => return write!(f, "order:random"),
+        };
+        let direction = match self.descending {
+            true => "desc",
+            false => "asc",
+        };
+        write!(f, "order:{}_{}", name, direction)
+    }
+}
+
+fn random_seed() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes() {
+        assert_eq!(parse_bytes("500"), Ok(500));
+        assert_eq!(parse_bytes("500b"), Ok(500));
+        assert_eq!(parse_bytes("200kb"), Ok(200 * 1024));
+        assert_eq!(parse_bytes("1.5MB"), Ok(1536 * 1024));
+        assert_eq!(parse_bytes("2g"), Ok(2 << 30));
+        assert!(parse_bytes("5tb").is_err());
+        assert!(parse_bytes("mb").is_err());
+        assert!(parse_bytes("").is_err());
+    }
+
+    #[test]
+    fn comparisons() {
+        assert_eq!(Comparison::parse("5"), Ok(Comparison::Eq(5)));
+        assert_eq!(Comparison::parse("=5"), Ok(Comparison::Eq(5)));
+        assert_eq!(Comparison::parse(">5"), Ok(Comparison::Gt(5)));
+        assert_eq!(Comparison::parse(">=5"), Ok(Comparison::Ge(5)));
+        assert_eq!(Comparison::parse("<5"), Ok(Comparison::Lt(5)));
+        assert_eq!(Comparison::parse("<=5"), Ok(Comparison::Le(5)));
+        assert_eq!(Comparison::parse("2..8"), Ok(Comparison::Between(2, 8)));
+        assert_eq!(Comparison::parse("..8"), Ok(Comparison::Le(8)));
+        assert_eq!(Comparison::parse("2.."), Ok(Comparison::Ge(2)));
+        assert!(Comparison::parse("..").is_err());
+        assert!(Comparison::parse("x").is_err());
+        assert!(Comparison::parse(">").is_err());
+
+        assert_eq!(
+            Comparison::parse_with(">1mb", parse_bytes),
+            Ok(Comparison::Gt(1 << 20))
+        );
+        assert_eq!(
+            Comparison::parse_with("1kb..2kb", parse_bytes),
+            Ok(Comparison::Between(1024, 2048))
+        );
+    }
+
+    #[test]
+    fn comparison_sql() {
+        let mut params = Vec::new();
+        let sql = Comparison::Between(2, 8).to_sql("posts.width", &mut params);
+        assert_eq!(sql, "posts.width BETWEEN ? AND ?");
+        assert_eq!(params, vec![Value::from(2), Value::from(8)]);
+    }
+
+    #[test]
+    fn metatags() {
+        assert_eq!(
+            Metatag::parse("ext", ".png"),
+            Some(Ok(Metatag::Extension("png".to_string())))
+        );
+        assert_eq!(
+            Metatag::parse("size", "<=2kb"),
+            Some(Ok(Metatag::Size(Comparison::Le(2048))))
+        );
+        assert!(matches!(Metatag::parse("name", ""), Some(Err(_))));
+        assert!(matches!(Metatag::parse("width", "wide"), Some(Err(_))));
+        assert_eq!(Metatag::parse("artist", "bob"), None);
+    }
+
+    #[test]
+    fn ratios() {
+        let ratio = Ratio::parse("16:9").unwrap();
+        assert_eq!(ratio.operator, "=");
+        assert!((ratio.ratio - 16.0 / 9.0).abs() < 1e-9);
+        assert_eq!(ratio.to_string(), "16:9");
+
+        let ratio = Ratio::parse(">=1.5").unwrap();
+        assert_eq!(ratio.operator, ">=");
+        assert_eq!(ratio.ratio, 1.5);
+
+        assert!(Ratio::parse("0").is_err());
+        assert!(Ratio::parse("1:0").is_err());
+        assert!(Ratio::parse("wide").is_err());
+    }
+
+    #[test]
+    fn orders() {
+        let order = Order::parse("name").unwrap();
+        assert_eq!(order.key, SortKey::Name);
+        assert!(!order.descending);
+
+        assert!(Order::parse("id").unwrap().descending);
+        assert!(!Order::parse("id_asc").unwrap().descending);
+        assert!(Order::parse("tagcount_desc").unwrap().descending);
+        assert!(Order::parse("bogus").is_err());
+        assert_eq!(
+            Order::parse("size_asc").unwrap().to_string(),
+            "order:size_asc"
+        );
+    }
+}
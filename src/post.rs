@@ -1,6 +1,9 @@
 use crate::config::Config;
 use crate::db::Database;
 use crate::hash;
+use crate::maintenance;
+use crate::media;
+use crate::tag::{self, Namespace};
 use crate::thumbnail;
 use arrayvec::ArrayString;
 use blake3::Hash;
@@ -16,6 +19,14 @@ pub struct Post {
     pub blake3_bytes: [u8; 32],
     pub extension: Option<String>,
     pub original_name: String,
+    pub file_size: Option<i64>,
+    // From `media::Metadata`, see there for their meaning.
+    pub mime: Option<String>,
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    pub duration: Option<f64>,
+    pub codec: Option<String>,
+    pub phash: Option<i64>,
     pub tags: HashSet<String>,
 }
 
@@ -23,6 +34,11 @@ impl Post {
     pub fn new(path: &Path, db: &mut Database) -> Result<Self, Box<dyn Error>> {
         let hash = hash::hash_file_blake3(path)?;
 
+        if let Some(post_id) = db.get_merged_post(*hash.as_bytes())? {
+            println!("File was merged into post #{}...", post_id);
+            return Ok(db.get_post_id(post_id)?);
+        }
+
         let extension = path
             .extension()
             .and_then(|s| s.to_os_string().into_string().ok());
@@ -34,11 +50,20 @@ impl Post {
             .into_string()
             .unwrap();
 
+        let metadata = media::read(path);
+
         let mut post = Post {
             id: 0,
             blake3_bytes: *hash.as_bytes(),
             extension,
             original_name,
+            file_size: Some(fs::metadata(path)?.len() as i64),
+            mime: metadata.mime,
+            width: metadata.width,
+            height: metadata.height,
+            duration: metadata.duration,
+            codec: metadata.codec,
+            phash: metadata.phash,
             tags: HashSet::new(),
         };
 
@@ -54,22 +79,31 @@ impl Post {
         fs::create_dir_all(file_location.parent().unwrap())?;
         fs::copy(path, &file_location)?;
 
-        let thumbnail_location = post.get_db_thumbnail(&db.config);
-        if let Err(e) = thumbnail::create(&file_location, &thumbnail_location) {
+        let thumbnails = post.get_db_thumbnails(&db.config);
+        if let Err(e) = thumbnail::create(&file_location, &thumbnails, &db.config) {
             eprintln!("{} {}", e, post.original_name);
         }
 
         Ok(post)
     }
 
-    pub fn add_tag(&mut self, tag: &String, db: &Database) -> Result<i64, Box<dyn Error>> {
-        if self.tags.contains(tag) {
+    pub fn add_tag(&mut self, tag: &str, db: &Database) -> Result<i64, Box<dyn Error>> {
+        let tag = db.canonical_tag(tag)?;
+        if self.tags.contains(&tag) {
             return Ok(0);
         }
 
-        self.tags.insert(tag.to_string());
-        let tag_id = db.get_or_create_tag(tag)?;
-        Ok(db.insert_tagging(self.id, tag_id)?)
+        let tag_id = db.get_or_create_tag(&tag)?;
+        self.tags.insert(tag);
+        let tagging_id = db.insert_tagging(self.id, tag_id)?;
+
+        for (implied_id, implied) in db.get_implied_tags(tag_id)? {
+            if self.tags.insert(implied) {
+                db.insert_tagging(self.id, implied_id)?;
+            }
+        }
+
+        Ok(tagging_id)
     }
 
     pub fn add_tags(
@@ -83,29 +117,49 @@ impl Post {
         Ok(())
     }
 
-    pub fn remove_tag(&mut self, tag: &String, db: &Database) -> Result<(), Box<dyn Error>> {
-        if !self.tags.contains(tag) {
-            return Ok(());
+    /// Returns a warning for each tag still on the post that implies `tag`.
+    pub fn remove_tag(
+        &mut self,
+        tag: &String,
+        db: &Database,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let tag = match self.tags.contains(tag) {
+            true => tag.to_string(),
+            false => db.canonical_tag(tag)?,
+        };
+        if !self.tags.contains(&tag) {
+            return Ok(Vec::new());
         }
 
-        self.tags.remove(tag);
-        let tag_id = db.get_tag_id(tag)?;
+        let tag_id = db.get_tag_id(&tag)?;
+        self.tags.remove(&tag);
         db.remove_tagging(self.id, tag_id)?;
-        Ok(())
+
+        let mut warnings = Vec::new();
+        for other in &self.tags {
+            if db.implies(db.get_tag_id(other)?, tag_id)? {
+                warnings.push(format!(
+                    "'{}' is implied by '{}', which is still on post #{}",
+                    tag, other, self.id
+                ));
+            }
+        }
+        Ok(warnings)
     }
 
     pub fn remove_tags(
         &mut self,
         tags: &Vec<String>,
         db: &mut Database,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut warnings = Vec::new();
         for tag in tags {
-            self.remove_tag(tag, db)?;
+            warnings.extend(self.remove_tag(tag, db)?);
         }
-        Ok(())
+        Ok(warnings)
     }
 
-    fn get_hash(&self) -> Hash {
+    pub fn get_hash(&self) -> Hash {
         Hash::from(self.blake3_bytes)
     }
 
@@ -121,43 +175,262 @@ impl Post {
         path
     }
 
-    pub fn get_db_thumbnail(&self, config: &Config) -> PathBuf {
+    /// The thumbnail of `size`. Sizes other than `thumbnail_size` are stored
+    /// as `<hash>_<size>`.
+    pub fn get_db_thumbnail(&self, config: &Config, size: u32) -> PathBuf {
         let hex = self.get_hash().to_hex();
+        let name = match size == config.thumbnail_size {
+            true => hex.to_string(),
+            false => format!("{}_{}", hex, size),
+        };
         let mut path = Path::new(&config.db_thumbnail_path)
             .join(self.get_db_folder(hex))
-            .join(hex.as_str());
-        path.set_extension("jpg");
+            .join(name);
+        path.set_extension(config.thumbnail_format.extension());
         path
     }
 
+    /// Every stored size of the thumbnail, with its path.
+    pub fn get_db_thumbnails(&self, config: &Config) -> Vec<(u32, PathBuf)> {
+        config
+            .thumbnail_sizes()
+            .into_iter()
+            .map(|size| (size, self.get_db_thumbnail(config, size)))
+            .collect()
+    }
+
     fn get_db_folder(&self, hex: ArrayString<64>) -> PathBuf {
         Path::new(&hex[0..2]).join(&hex[2..4])
     }
 
-    pub fn get_tag_string(&self) -> String {
+    pub fn get_tag_string(&self, namespaces: &[Namespace]) -> String {
         let mut tags = self.tags.clone().into_iter().collect::<Vec<String>>();
-        tags.sort();
+        tag::sort(&mut tags, namespaces);
         tags.join(",")
     }
 
+    /// Shows the post with its tags in the same order as the GUI.
+    pub fn details<'a>(&'a self, namespaces: &'a [Namespace]) -> Details<'a> {
+        Details {
+            post: self,
+            namespaces,
+        }
+    }
+
+    /// Merges `other` into this post in one transaction. The tags are combined
+    /// and `other` is deleted. With `keep_better`, this post takes over the
+    /// file of `other` if it has more pixels or, failing that, more bytes.
+    /// Importing the deleted file again finds this post.
+    pub fn merge(
+        &mut self,
+        mut other: Post,
+        keep_better: bool,
+        db: &Database,
+    ) -> Result<(), Box<dyn Error>> {
+        if other.id == self.id {
+            return Err("Can't merge a post into itself".into());
+        }
+
+        db.begin()?;
+        let result = self.merge_into(&mut other, keep_better, db);
+        match result {
+            Ok(()) => db.commit()?,
+            Err(_) => db.rollback()?,
+        }
+        result?;
+
+        // The kept file is in place, so the other one can go.
+        let name = other.original_name.clone();
+        if let Err(e) = other.delete(db) {
+            eprintln!("Failed to remove {}: {}", name, e);
+        }
+        Ok(())
+    }
+
+    fn merge_into(
+        &mut self,
+        other: &mut Post,
+        keep_better: bool,
+        db: &Database,
+    ) -> Result<(), Box<dyn Error>> {
+        for tag in &other.tags {
+            self.add_tag(tag, db)?;
+        }
+
+        let swap = keep_better && other.quality() > self.quality();
+        if swap {
+            self.swap_file(other);
+        }
+
+        // Before removing `other`, which drops the redirects pointing at it.
+        db.insert_merged_post(other.blake3_bytes, other.id, self.id)?;
+        db.remove_post(other.id)?;
+        if swap {
+            db.update_post_file(self)?;
+        }
+        Ok(())
+    }
+
+    /// Pixel count, then file size.
+    fn quality(&self) -> (i64, i64) {
+        let pixels = self.width.unwrap_or_default() * self.height.unwrap_or_default();
+        (pixels, self.file_size.unwrap_or_default())
+    }
+
+    fn swap_file(&mut self, other: &mut Post) {
+        std::mem::swap(&mut self.blake3_bytes, &mut other.blake3_bytes);
+        std::mem::swap(&mut self.extension, &mut other.extension);
+        std::mem::swap(&mut self.original_name, &mut other.original_name);
+        std::mem::swap(&mut self.file_size, &mut other.file_size);
+        std::mem::swap(&mut self.mime, &mut other.mime);
+        std::mem::swap(&mut self.width, &mut other.width);
+        std::mem::swap(&mut self.height, &mut other.height);
+        std::mem::swap(&mut self.duration, &mut other.duration);
+        std::mem::swap(&mut self.codec, &mut other.codec);
+        std::mem::swap(&mut self.phash, &mut other.phash);
+    }
+
+    /// `WIDTHxHEIGHT`, if known.
+    pub fn dimensions(&self) -> Option<String> {
+        Some(format!("{}x{}", self.width?, self.height?))
+    }
+
     pub fn delete(self, db: &Database) -> Result<(), Box<dyn Error>> {
         db.remove_post(self.id)?;
-        Ok(fs::remove_file(self.get_db_file(&db.config))?)
+        maintenance::remove_stored_file(&self.get_db_file(&db.config))?;
+
+        for (_, thumbnail) in self.get_db_thumbnails(&db.config) {
+            if thumbnail.exists() {
+                maintenance::remove_stored_file(&thumbnail)?;
+            }
+        }
+        Ok(())
     }
 }
 
-impl fmt::Display for Post {
+pub struct Details<'a> {
+    post: &'a Post,
+    namespaces: &'a [Namespace],
+}
+
+impl fmt::Display for Details<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let post = self.post;
         write!(
             f,
-            "Post {{\n  id:{}\n  file: {}{}\n  tags: [{}]\n}}",
-            self.id,
-            self.get_hash().to_hex(),
-            self.extension
+            "Post {{\n  id:{}\n  file: {}{}\n",
+            post.id,
+            post.get_hash().to_hex(),
+            post.extension
                 .clone()
                 .map(|e| format!(".{}", e))
-                .unwrap_or(String::new()),
-            self.get_tag_string(),
-        )
+                .unwrap_or_default(),
+        )?;
+
+        let mut info = Vec::new();
+        if let Some(size) = post.file_size {
+            info.push(format_bytes(size as u64));
+        }
+        if let Some(dimensions) = post.dimensions() {
+            info.push(dimensions);
+        }
+        if let Some(mime) = &post.mime {
+            info.push(mime.clone());
+        }
+        if !info.is_empty() {
+            writeln!(f, "  info: {}", info.join(", "))?;
+        }
+
+        if let Some(duration) = post.duration {
+            write!(f, "  video: {:.1}s", duration)?;
+            if let Some(codec) = &post.codec {
+                write!(f, " {}", codec)?;
+            }
+            writeln!(f)?;
+        }
+
+        write!(f, "  tags: [{}]\n}}", post.get_tag_string(self.namespaces))
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KiB", "MiB", "GiB"] {
+        if size < 1024.0 {
+            return match unit {
+                "B" => format!("{} B", bytes),
+                _ => format!("{:.1} {}", size, unit),
+            };
+        }
+        size /= 1024.0;
+    }
+    format!("{:.1} TiB", size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::tests::{database, insert_post};
+
+    #[test]
+    fn merging_unions_tags() {
+        let db = database();
+        let mut keep = insert_post(1, &db);
+        let mut other = insert_post(2, &db);
+        keep.add_tag("cat", &db).unwrap();
+        other.add_tag("cat", &db).unwrap();
+        other.add_tag("dog", &db).unwrap();
+
+        keep.merge(other, false, &db).unwrap();
+        let tags = HashSet::from(["cat".to_string(), "dog".to_string()]);
+        assert_eq!(keep.tags, tags);
+        assert_eq!(db.get_post_tags(keep.id).unwrap(), tags);
+        assert!(db.get_post_id(2).is_err());
+    }
+
+    #[test]
+    fn importing_a_merged_file_finds_the_kept_post() {
+        let path = std::env::temp_dir().join(format!("pkrs-merged-{}.txt", std::process::id()));
+        fs::write(&path, "merged").unwrap();
+
+        let mut db = database();
+        let mut keep = insert_post(1, &db);
+        let mut other = insert_post(2, &db);
+        other.blake3_bytes = *hash::hash_file_blake3(&path).unwrap().as_bytes();
+        db.update_post_file(&other).unwrap();
+
+        keep.merge(other, false, &db).unwrap();
+        let post = Post::new(&path, &mut db);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(post.unwrap().id, keep.id);
+    }
+
+    #[test]
+    fn merging_again_moves_earlier_redirects() {
+        let db = database();
+        let mut first = insert_post(1, &db);
+        let second = insert_post(2, &db);
+        let mut third = insert_post(3, &db);
+
+        first.merge(second, false, &db).unwrap();
+        third.merge(first, false, &db).unwrap();
+        assert_eq!(db.get_merged_post([1; 32]).unwrap(), Some(third.id));
+        assert_eq!(db.get_merged_post([2; 32]).unwrap(), Some(third.id));
+    }
+
+    #[test]
+    fn keeping_the_better_file_swaps_it_in() {
+        let db = database();
+        let mut keep = insert_post(1, &db);
+        let mut other = insert_post(2, &db);
+        (other.width, other.height) = (Some(200), Some(100));
+        db.update_post_file(&other).unwrap();
+
+        keep.merge(other, true, &db).unwrap();
+        assert_eq!(keep.id, 1);
+        assert_eq!(keep.blake3_bytes, [2; 32]);
+        assert_eq!(db.get_post_id(1).unwrap().width, Some(200));
+        assert_eq!(db.get_merged_post([1; 32]).unwrap(), Some(1));
+        assert_eq!(db.get_merged_post([2; 32]).unwrap(), None);
     }
 }
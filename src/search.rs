@@ -1,23 +1,657 @@
-use std::error::Error;
+use std::{error::Error, fmt, iter::Peekable, str::CharIndices};
 
-use crate::{db::Database, post::Post};
+use rusqlite::types::Value;
 
-pub fn new(tags: Vec<String>, db: &mut Database) -> Result<Vec<Post>, Box<dyn Error>> {
-    let mut include = Vec::new();
-    let mut exclude = Vec::new();
+use crate::{
+    db::{Database, Page},
+    metatag::{self, Metatag, Order},
+    post::Post,
+    tag,
+};
 
-    for tag in tags {
-        match tag.starts_with('-') {
-            true => exclude.push(tag[1..].to_owned()),
-            false => include.push(tag),
+/// A filter expression plus the order to return its results in.
+#[derive(Debug)]
+pub struct Query {
+    pub expr: Expr,
+    pub order: Order,
+}
+
+impl Default for Query {
+    fn default() -> Self {
+        Query {
+            expr: Expr::And(vec![]),
+            order: Order::default(),
+        }
+    }
+}
+
+impl Query {
+    pub fn run(&self, page: &Page, db: &Database) -> rusqlite::Result<Vec<Post>> {
+        let mut params = Vec::new();
+        let filter = self.expr.to_sql(&mut params);
+        let order_by = self.order.to_sql(&mut params);
+        db.search(&filter, &order_by, params, page)
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.expr {
+            Expr::And(ref exprs) if exprs.is_empty() => write!(f, "{}", self.order),
+            ref expr => write!(f, "{} {}", expr, self.order),
+        }
+    }
+}
+
+/// A parsed search query.
+///
+/// Terms separated by whitespace are AND-ed together, `OR` binds looser than
+/// AND, `-` negates the following term or group, and parentheses group.
+/// Unquoted terms containing `*` or `?` are glob patterns matched against
+/// every tag, so `artist:*` finds everything in a namespace. `key:value`
+/// terms with a known key are [`Metatag`]s. Tags containing spaces,
+/// wildcards or reserved words can be written in double quotes.
+///
+/// Nothing requires a post to have taggings: an empty query matches every
+/// post, and a query made only of exclusions matches every post without
+/// those tags, untagged posts included.
+#[derive(Debug, PartialEq)]
+pub enum Expr {
+    Tag(String),
+    Pattern(String),
+    Meta(Metatag),
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+}
+
+impl Expr {
+    /// Compiles the expression to an SQL condition over `posts`, pushing the
+    /// bound values onto `params` in the order their `?` appear.
+    pub fn to_sql(&self, params: &mut Vec<Value>) -> String {
+        match self {
+            Expr::Tag(tag) => {
+                let (namespace, name) = tag::split(tag);
+                params.push(Value::from(namespace.to_string()));
+                params.push(Value::from(name.to_string()));
+                params.push(Value::from(tag.clone()));
+                "posts.post_id IN (
+                    SELECT taggings.post_id
+                    FROM taggings, tags
+                    WHERE taggings.tag_id = tags.tag_id
+                    AND ((tags.namespace = ? AND tags.tag_name = ?)
+                        OR tags.tag_id IN (SELECT tag_id FROM tag_aliases WHERE alias = ?)))"
+                    .to_string()
+            }
+            Expr::Pattern(pattern) => {
+                params.push(Value::from(glob_escape(pattern)));
+                params.push(Value::from(glob_escape(pattern)));
+                "posts.post_id IN (
+                    SELECT taggings.post_id
+                    FROM taggings, tags
+                    WHERE taggings.tag_id = tags.tag_id
+                    AND (tags.full_name GLOB ?
+                        OR tags.tag_id IN (SELECT tag_id FROM tag_aliases WHERE alias GLOB ?)))"
+                    .to_string()
+            }
+            Expr::Meta(metatag) => metatag.to_sql(params),
+            // Metatags on unknown columns are NULL rather than false, which
+            // NOT would keep NULL and drop the post.
+            Expr::Not(expr) => format!("NOT coalesce(({}), 0)", expr.to_sql(params)),
+            Expr::And(exprs) => Self::join_sql(exprs, " AND ", "1", params),
+            Expr::Or(exprs) => Self::join_sql(exprs, " OR ", "0", params),
+        }
+    }
+
+    fn join_sql(exprs: &[Expr], sep: &str, empty: &str, params: &mut Vec<Value>) -> String {
+        if exprs.is_empty() {
+            return empty.to_string();
+        }
+
+        let parts: Vec<String> = exprs
+            .iter()
+            .map(|e| format!("({})", e.to_sql(params)))
+            .collect();
+        parts.join(sep)
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Tag(tag) => match needs_quotes(tag) {
+                true => write!(f, "\"{}\"", tag.replace('\\', "\\\\").replace('"', "\\\"")),
+                false => write!(f, "{}", tag),
+            },
+            Expr::Pattern(pattern) => write!(f, "{}", pattern),
+            Expr::Meta(metatag) => write!(f, "{}", metatag),
+            Expr::Not(expr) => match expr.as_ref() {
+                Expr::And(exprs) if exprs.len() > 1 => write!(f, "-({})", expr),
+                _ => write!(f, "-{}", expr),
+            },
+            Expr::And(exprs) => write_joined(f, exprs, " "),
+            Expr::Or(exprs) => {
+                write!(f, "(")?;
+                write_joined(f, exprs, " OR ")?;
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+fn write_joined(f: &mut fmt::Formatter<'_>, exprs: &[Expr], sep: &str) -> fmt::Result {
+    for (i, expr) in exprs.iter().enumerate() {
+        if i > 0 {
+            write!(f, "{}", sep)?;
+        }
+        match expr {
+            Expr::And(inner) if inner.len() > 1 => write!(f, "({})", expr)?,
+            _ => write!(f, "{}", expr)?,
+        }
+    }
+    Ok(())
+}
+
+fn needs_quotes(tag: &str) -> bool {
+    tag.is_empty()
+        || tag == "OR"
+        || tag == "AND"
+        || tag.starts_with(['-', '('])
+        || tag.contains(|c: char| c.is_whitespace() || is_wildcard(c) || c == '"')
+        || tag
+            .split_once(':')
+            .is_some_and(|(key, _)| metatag::KEYS.contains(&key))
+}
+
+fn is_wildcard(c: char) -> bool {
+    c == '*' || c == '?'
+}
+
+/// SQLite's GLOB already treats `*` and `?` as wildcards, so only the
+/// character class opener needs escaping.
+fn glob_escape(pattern: &str) -> String {
+    pattern.replace('[', "[[]")
+}
+
+#[derive(Debug)]
+pub struct SyntaxError {
+    pub message: String,
+    /// Byte offset into the query where the error was found.
+    pub position: usize,
+    pub query: String,
+}
+
+impl SyntaxError {
+    fn new(message: impl Into<String>, position: usize, query: &str) -> Self {
+        SyntaxError {
+            message: message.into(),
+            position,
+            query: query.to_string(),
+        }
+    }
+
+    pub fn column(&self) -> usize {
+        self.query[..self.position].chars().count() + 1
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Syntax error at column {}: {}\n  {}\n  {}^",
+            self.column(),
+            self.message,
+            self.query,
+            " ".repeat(self.column() - 1)
+        )
+    }
+}
+
+impl Error for SyntaxError {}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Not,
+    Or,
+    And,
+    Term(String),
+    Pattern(String),
+    Meta(Metatag),
+    Order(Order),
+}
+
+struct Lexer<'a> {
+    query: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(query: &'a str) -> Self {
+        Lexer {
+            query,
+            chars: query.char_indices().peekable(),
+        }
+    }
+
+    fn tokens(mut self) -> Result<Vec<(Token, usize)>, SyntaxError> {
+        let mut tokens = Vec::new();
+
+        while let Some(&(pos, c)) = self.chars.peek() {
+            let token = match c {
+                c if c.is_whitespace() => {
+                    self.chars.next();
+                    continue;
+                }
+                '(' => {
+                    self.chars.next();
+                    Token::Open
+                }
+                ')' => {
+                    self.chars.next();
+                    Token::Close
+                }
+                '-' => {
+                    self.chars.next();
+                    match self.chars.peek() {
+                        Some((_, c)) if !c.is_whitespace() => Token::Not,
+                        _ => {
+                            let message = "expected a tag or group after '-'";
+                            return Err(SyntaxError::new(message, pos, self.query));
+                        }
+                    }
+                }
+                '"' => Token::Term(self.quoted(pos)?),
+                _ => match self.bare() {
+                    word if word == "OR" => Token::Or,
+                    word if word == "AND" => Token::And,
+                    word => self.classify(word, pos)?,
+                },
+            };
+            tokens.push((token, pos));
+        }
+
+        Ok(tokens)
+    }
+
+    fn classify(&self, word: String, pos: usize) -> Result<Token, SyntaxError> {
+        if let Some((key, value)) = word.split_once(':') {
+            let token = match key {
+                "order" => Some(Order::parse(value).map(Token::Order)),
+                _ => Metatag::parse(key, value).map(|m| m.map(Token::Meta)),
+            };
+
+            match token {
+                Some(Ok(token)) => return Ok(token),
+                Some(Err(message)) => {
+                    let position = pos + key.len() + 1;
+                    return Err(SyntaxError::new(message, position, self.query));
+                }
+                None => {}
+            }
+        }
+
+        Ok(match word.contains(is_wildcard) {
+            true => Token::Pattern(word),
+            false => Token::Term(word),
+        })
+    }
+
+    fn quoted(&mut self, start: usize) -> Result<String, SyntaxError> {
+        self.chars.next();
+        let mut term = String::new();
+
+        while let Some((_, c)) = self.chars.next() {
+            match c {
+                '"' => return Ok(term),
+                '\\' => match self.chars.next() {
+                    Some((_, escaped)) => term.push(escaped),
+                    None => break,
+                },
+                c => term.push(c),
+            }
+        }
+
+        Err(SyntaxError::new("unterminated quote", start, self.query))
+    }
+
+    /// Reads an unquoted term. Parentheses opened inside a term belong to it,
+    /// so tags like `name_(series)` don't need quoting.
+    fn bare(&mut self) -> String {
+        let mut term = String::new();
+        let mut depth = 0;
+
+        while let Some(&(_, c)) = self.chars.peek() {
+            match c {
+                c if c.is_whitespace() => break,
+                '(' => depth += 1,
+                ')' if depth == 0 => break,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            term.push(c);
+            self.chars.next();
+        }
+        term
+    }
+}
+
+pub fn parse(query: &str) -> Result<Query, SyntaxError> {
+    let mut tokens = Lexer::new(query).tokens()?;
+    let order = take_order(&mut tokens, query)?;
+    let mut parser = Parser {
+        query,
+        tokens: tokens.into_iter().peekable(),
+    };
+
+    let expr = parser.or()?;
+    match parser.tokens.next() {
+        None => Ok(Query {
+            expr,
+            order: order.unwrap_or_default(),
+        }),
+        Some((Token::Close, pos)) => Err(SyntaxError::new("unmatched ')'", pos, query)),
+        Some((_, pos)) => Err(SyntaxError::new("unexpected token", pos, query)),
+    }
+}
+
+/// Removes the `order:` term from the tokens. It applies to the whole query,
+/// so it may only appear once and outside of groups, negations and ORs.
+fn take_order(tokens: &mut Vec<(Token, usize)>, query: &str) -> Result<Option<Order>, SyntaxError> {
+    let mut order = None;
+    let mut depth = 0;
+    let mut i = 0;
+
+    while i < tokens.len() {
+        match tokens[i].0 {
+            Token::Open => depth += 1,
+            Token::Close => depth -= 1,
+            Token::Order(found) => {
+                let pos = tokens[i].1;
+                let prev = i.checked_sub(1).map(|j| &tokens[j].0);
+                let next = tokens.get(i + 1).map(|t| &t.0);
+
+                if depth > 0
+                    || matches!(prev, Some(Token::Not | Token::Or | Token::And))
+                    || matches!(next, Some(Token::Or | Token::And))
+                {
+                    let message = "order: applies to the whole query and can't be grouped";
+                    return Err(SyntaxError::new(message, pos, query));
+                }
+                if order.is_some() {
+                    return Err(SyntaxError::new("only one order: is allowed", pos, query));
+                }
+
+                order = Some(found);
+                tokens.remove(i);
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    Ok(order)
+}
+
+struct Parser<'a> {
+    query: &'a str,
+    tokens: Peekable<std::vec::IntoIter<(Token, usize)>>,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str, position: usize) -> SyntaxError {
+        SyntaxError::new(message, position, self.query)
+    }
+
+    fn end(&self) -> usize {
+        self.query.len()
+    }
+
+    fn or(&mut self) -> Result<Expr, SyntaxError> {
+        let mut exprs = vec![self.and()?];
+
+        while let Some((Token::Or, pos)) = self.tokens.peek() {
+            let pos = *pos;
+            self.tokens.next();
+            if exprs[0] == Expr::And(vec![]) {
+                return Err(self.error("expected a tag or group before OR", pos));
+            }
+
+            let expr = self.and()?;
+            if expr == Expr::And(vec![]) {
+                return Err(self.error("expected a tag or group after OR", pos));
+            }
+            exprs.push(expr);
+        }
+
+        Ok(match exprs.len() {
+            1 => exprs.remove(0),
+            _ => Expr::Or(exprs),
+        })
+    }
+
+    fn and(&mut self) -> Result<Expr, SyntaxError> {
+        let mut exprs = Vec::new();
+
+        loop {
+            match self.tokens.peek() {
+                None | Some((Token::Close | Token::Or, _)) => break,
+                Some((Token::And, pos)) => {
+                    let pos = *pos;
+                    self.tokens.next();
+                    if exprs.is_empty() {
+                        return Err(self.error("expected a tag or group before AND", pos));
+                    }
+                    if let None | Some((Token::Close | Token::Or | Token::And, _)) =
+                        self.tokens.peek()
+                    {
+                        return Err(self.error("expected a tag or group after AND", pos));
+                    }
+                }
+                Some(_) => exprs.push(self.unary()?),
+            }
+        }
+
+        Ok(match exprs.len() {
+            1 => exprs.remove(0),
+            _ => Expr::And(exprs),
+        })
+    }
+
+    fn unary(&mut self) -> Result<Expr, SyntaxError> {
+        let end = self.end();
+        match self.tokens.next() {
+            Some((Token::Not, pos)) => match self.tokens.peek() {
+                Some((
+                    Token::Term(_) | Token::Pattern(_) | Token::Meta(_) | Token::Open | Token::Not,
+                    _,
+                )) => Ok(Expr::Not(Box::new(self.unary()?))),
+                _ => Err(self.error("expected a tag or group after '-'", pos)),
+            },
+            Some((Token::Open, pos)) => {
+                let expr = self.or()?;
+                match self.tokens.next() {
+                    Some((Token::Close, close)) => match expr {
+                        Expr::And(ref exprs) if exprs.is_empty() => {
+                            Err(self.error("empty group", close))
+                        }
+                        expr => Ok(expr),
+                    },
+                    _ => Err(self.error("unclosed '('", pos)),
+                }
+            }
+            Some((Token::Term(tag), _)) => Ok(Expr::Tag(tag)),
+            Some((Token::Pattern(pattern), _)) => Ok(Expr::Pattern(pattern)),
+            Some((Token::Meta(metatag), _)) => Ok(Expr::Meta(metatag)),
+            Some((_, pos)) => Err(self.error("unexpected token", pos)),
+            None => Err(self.error("unexpected end of query", end)),
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metatag::{Comparison, SortKey};
+    use std::collections::HashSet;
+
+    fn tag(name: &str) -> Expr {
+        Expr::Tag(name.to_string())
+    }
+
+    fn not(expr: Expr) -> Expr {
+        Expr::Not(Box::new(expr))
+    }
+
+    fn expr(query: &str) -> Expr {
+        parse(query).unwrap().expr
+    }
 
-    println!(
-        "Searching for '{}' Excluding: {}",
-        include.join(","),
-        exclude.join(",")
-    );
+    fn column(query: &str) -> usize {
+        parse(query).unwrap_err().column()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            expr("a b OR c"),
+            Expr::Or(vec![Expr::And(vec![tag("a"), tag("b")]), tag("c")])
+        );
+        assert_eq!(
+            expr("a (b OR c)"),
+            Expr::And(vec![tag("a"), Expr::Or(vec![tag("b"), tag("c")])])
+        );
+        assert_eq!(expr("a AND b"), expr("a b"));
+    }
+
+    #[test]
+    fn quoted_terms_are_tags() {
+        assert_eq!(
+            expr(r#""long hair" "OR" "a*" "say \"hi\"""#),
+            Expr::And(vec![
+                tag("long hair"),
+                tag("OR"),
+                tag("a*"),
+                tag("say \"hi\""),
+            ])
+        );
+    }
+
+    #[test]
+    fn bare_terms() {
+        assert_eq!(expr("name_(series)"), tag("name_(series)"));
+        assert_eq!(expr("artist:*"), Expr::Pattern("artist:*".to_string()));
+        assert_eq!(expr("id:>5"), Expr::Meta(Metatag::Id(Comparison::Gt(5))));
+        assert_eq!(expr("unknown:5"), tag("unknown:5"));
+    }
+
+    #[test]
+    fn negation() {
+        assert_eq!(
+            expr("-a -(b c) --d"),
+            Expr::And(vec![
+                not(tag("a")),
+                not(Expr::And(vec![tag("b"), tag("c")])),
+                not(not(tag("d"))),
+            ])
+        );
+    }
 
-    Ok(db.search(include, exclude)?)
+    #[test]
+    fn order_is_taken_from_the_top_level() {
+        let query = parse("a order:name b").unwrap();
+        assert_eq!(query.expr, Expr::And(vec![tag("a"), tag("b")]));
+        assert_eq!(query.order.key, SortKey::Name);
+        assert!(!query.order.descending);
+
+        assert_eq!(parse("a").unwrap().order, Order::default());
+        assert!(parse("(a order:id)").is_err());
+        assert!(parse("-order:id").is_err());
+        assert!(parse("a OR order:id").is_err());
+        assert!(parse("order:id order:name").is_err());
+    }
+
+    #[test]
+    fn errors_report_their_column() {
+        assert_eq!(column("a (b"), 3);
+        assert_eq!(column("a b)"), 4);
+        assert_eq!(column(r#"a "b"#), 3);
+        assert_eq!(column("a -"), 3);
+        assert_eq!(column("OR a"), 1);
+        assert_eq!(column("a OR"), 3);
+        assert_eq!(column("a ()"), 4);
+        assert_eq!(column("id:x"), 4);
+        assert_eq!(column("order:bogus"), 7);
+        // Columns count characters, not bytes.
+        assert_eq!(column("ä ö )"), 5);
+    }
+
+    #[test]
+    fn display_parses_back_to_the_same_query() {
+        for query in ["a b OR c", "-(a b) \"long hair\"", "a (b OR -c) id:1..3"] {
+            assert_eq!(expr(&expr(query).to_string()), expr(query));
+        }
+    }
+
+    #[test]
+    fn params_follow_placeholders() {
+        let mut params = Vec::new();
+        let sql = expr("-ext:png OR id:2..4").to_sql(&mut params);
+        assert_eq!(sql.matches('?').count(), params.len());
+        assert_eq!(
+            params,
+            vec![
+                Value::from("png".to_string()),
+                Value::from(2),
+                Value::from(4)
+            ]
+        );
+    }
+
+    /// A database in memory with an untagged post #1 and post #2 tagged `a`.
+    fn database() -> Database {
+        let config = toml::from_str(r#"db_sql_path = ":memory:""#).unwrap();
+        let db = Database::connect(config);
+
+        for id in 1..=2 {
+            let mut post = Post {
+                id: 0,
+                blake3_bytes: [id as u8; 32],
+                extension: None,
+                original_name: format!("{}.png", id),
+                file_size: None,
+                mime: None,
+                width: None,
+                height: None,
+                duration: None,
+                codec: None,
+                phash: None,
+                tags: HashSet::new(),
+            };
+            post.id = db.insert_post(&post).unwrap();
+            if id == 2 {
+                post.add_tag("a", &db).unwrap();
+            }
+        }
+        db
+    }
+
+    fn ids(query: &str, db: &Database) -> Vec<i64> {
+        let posts = parse(query).unwrap().run(&Page::default(), db).unwrap();
+        posts.into_iter().map(|post| post.id).collect()
+    }
+
+    #[test]
+    fn empty_and_exclusion_only_queries_include_untagged_posts() {
+        let db = database();
+        assert_eq!(ids("", &db), vec![2, 1]);
+        assert_eq!(ids("order:id_asc", &db), vec![1, 2]);
+        assert_eq!(ids("-a", &db), vec![1]);
+        assert_eq!(ids("-b", &db), vec![2, 1]);
+        assert_eq!(ids("-ext:png", &db), vec![2, 1]);
+        assert_eq!(ids("a", &db), vec![2]);
+    }
 }
@@ -0,0 +1,246 @@
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{config::Config, tag};
+
+const DUBLIN_CORE: &str = "http://purl.org/dc/elements/1.1/";
+
+/// Files written next to an image by downloaders and photo managers, named
+/// `image.jpg.txt` or `image.txt`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// One tag per line, or separated by commas.
+    Txt,
+    /// The fields listed in `sidecar_json_fields`.
+    Json,
+    /// Keywords in `dc:subject`.
+    Xmp,
+}
+
+impl Format {
+    pub fn all() -> Vec<Format> {
+        vec![Format::Txt, Format::Json, Format::Xmp]
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Txt => "txt",
+            Format::Json => "json",
+            Format::Xmp => "xmp",
+        }
+    }
+
+    fn of(path: &Path, formats: &[Format]) -> Option<Format> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        formats
+            .iter()
+            .copied()
+            .find(|format| format.extension() == extension)
+    }
+}
+
+/// Tags from the sidecars of `path`. Sidecars that can't be read are reported
+/// and skipped.
+pub fn read_tags(path: &Path, config: &Config) -> Vec<String> {
+    let mut tags = Vec::new();
+    for (sidecar, format) in find(path, &config.sidecar_formats) {
+        match parse(&sidecar, format, config) {
+            Ok(parsed) => tags.extend(parsed),
+            Err(e) => eprintln!("Failed to read {}. {}", sidecar.display(), e),
+        }
+    }
+    tags
+}
+
+fn parse(sidecar: &Path, format: Format, config: &Config) -> Result<Vec<String>, Box<dyn Error>> {
+    let text = fs::read_to_string(sidecar)?;
+    match format {
+        Format::Txt => Ok(txt_tags(&text)),
+        Format::Json => json_tags(&text, &config.sidecar_json_fields),
+        Format::Xmp => xmp_tags(&text),
+    }
+}
+
+/// Drops the sidecars of other files from `paths`, so they aren't imported
+/// as posts of their own.
+pub fn remove_sidecars<P: AsRef<Path>>(paths: Vec<P>, config: &Config) -> Vec<P> {
+    let formats = &config.sidecar_formats;
+    let stems: HashSet<_> = paths
+        .iter()
+        .map(|path| path.as_ref())
+        .filter(|path| Format::of(path, formats).is_none())
+        .map(|path| path.with_extension(""))
+        .collect();
+
+    paths
+        .into_iter()
+        .filter(|path| {
+            let path = path.as_ref();
+            Format::of(path, formats).is_none() || {
+                let owner = path.with_extension("");
+                !owner.is_file() && !stems.contains(&owner)
+            }
+        })
+        .collect()
+}
+
+fn find(path: &Path, formats: &[Format]) -> Vec<(PathBuf, Format)> {
+    let mut found = Vec::new();
+    for &format in formats {
+        let mut appended = path.as_os_str().to_owned();
+        appended.push(".");
+        appended.push(format.extension());
+
+        for sidecar in [
+            PathBuf::from(appended),
+            path.with_extension(format.extension()),
+        ] {
+            if sidecar != path && sidecar.is_file() && !found.contains(&(sidecar.clone(), format)) {
+                found.push((sidecar, format));
+            }
+        }
+    }
+    found
+}
+
+fn txt_tags(text: &str) -> Vec<String> {
+    text.split(['\n', ','])
+        .filter_map(|line| tag::clean("", line))
+        .collect()
+}
+
+/// Reads the fields in `fields`, mapped to the namespace their tags go in.
+/// Nested fields are written `a.b`. Strings are split on whitespace like
+/// booru tag strings, while each string in an array is one tag.
+fn json_tags(text: &str, fields: &BTreeMap<String, String>) -> Result<Vec<String>, Box<dyn Error>> {
+    let json: Value = serde_json::from_str(text)?;
+    let mut tags = Vec::new();
+
+    for (field, namespace) in fields {
+        let value = field
+            .split('.')
+            .try_fold(&json, |value, key| value.get(key));
+
+        match value {
+            Some(Value::String(string)) => tags.extend(
+                string
+                    .split_whitespace()
+                    .filter_map(|t| tag::clean(namespace, t)),
+            ),
+            Some(Value::Array(values)) => {
+                tags.extend(values.iter().filter_map(|value| match value {
+                    Value::String(string) => tag::clean(namespace, string),
+                    Value::Number(number) => tag::clean(namespace, &number.to_string()),
+                    _ => None,
+                }))
+            }
+            Some(Value::Number(number)) => tags.extend(tag::clean(namespace, &number.to_string())),
+            _ => {}
+        }
+    }
+    Ok(tags)
+}
+
+fn xmp_tags(text: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let xml = roxmltree::Document::parse(text)?;
+    let tags = xml
+        .descendants()
+        .filter(|node| node.has_tag_name((DUBLIN_CORE, "subject")))
+        .flat_map(|subject| subject.descendants().filter(|node| node.has_tag_name("li")))
+        .filter_map(|keyword| tag::clean("", keyword.text()?))
+        .collect();
+    Ok(tags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    #[test]
+    fn txt() {
+        let cases = [
+            ("cat\ndog\n", strings(&["cat", "dog"])),
+            ("cat, dog,bird", strings(&["cat", "dog", "bird"])),
+            (
+                "long hair, blue  eyes\n\n",
+                strings(&["long_hair", "blue_eyes"]),
+            ),
+            ("artist:someone\r\n", strings(&["artist:someone"])),
+            (" , \n", strings(&[])),
+        ];
+        for (text, tags) in cases {
+            assert_eq!(txt_tags(text), tags, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn json() {
+        let fields = BTreeMap::from([
+            ("tags".to_string(), String::new()),
+            ("artist".to_string(), "artist".to_string()),
+            ("post.characters".to_string(), "character".to_string()),
+            ("id".to_string(), "source".to_string()),
+        ]);
+        let cases = [
+            (r#"{"tags": "cat dog"}"#, strings(&["cat", "dog"])),
+            (
+                r#"{"tags": ["long hair", "cat"]}"#,
+                strings(&["long_hair", "cat"]),
+            ),
+            (
+                r#"{"artist": "someone", "post": {"characters": ["alice", "bob"]}}"#,
+                strings(&["artist:someone", "character:alice", "character:bob"]),
+            ),
+            (
+                r#"{"id": 123, "other": "ignored"}"#,
+                strings(&["source:123"]),
+            ),
+            (r#"{"tags": {"nested": "object"}}"#, strings(&[])),
+        ];
+        for (text, tags) in cases {
+            assert_eq!(json_tags(text, &fields).unwrap(), tags, "{}", text);
+        }
+        assert!(json_tags("not json", &fields).is_err());
+    }
+
+    #[test]
+    fn xmp() {
+        let xmp = |subject: &str| {
+            format!(
+                r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+                <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+                <rdf:Description xmlns:dc="http://purl.org/dc/elements/1.1/">
+                <dc:title><rdf:Alt><rdf:li>Not a tag</rdf:li></rdf:Alt></dc:title>
+                {}
+                </rdf:Description>
+                </rdf:RDF>
+                </x:xmpmeta>"#,
+                subject
+            )
+        };
+        let cases = [
+            (
+                "<dc:subject><rdf:Bag><rdf:li>cat</rdf:li><rdf:li>long hair</rdf:li></rdf:Bag></dc:subject>",
+                strings(&["cat", "long_hair"]),
+            ),
+            (
+                "<dc:subject><rdf:Seq><rdf:li>dog</rdf:li><rdf:li></rdf:li></rdf:Seq></dc:subject>",
+                strings(&["dog"]),
+            ),
+            ("", strings(&[])),
+        ];
+        for (subject, tags) in cases {
+            assert_eq!(xmp_tags(&xmp(subject)).unwrap(), tags, "{}", subject);
+        }
+        assert!(xmp_tags("<unclosed>").is_err());
+    }
+}
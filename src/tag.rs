@@ -0,0 +1,55 @@
+/// Splits `namespace:name` into its parts. Tags without a namespace, or with
+/// nothing on one side of the colon, are in the general namespace `""`.
+pub fn split(tag: &str) -> (&str, &str) {
+    match tag.split_once(':') {
+        Some((namespace, name)) if !namespace.is_empty() && !name.is_empty() => (namespace, name),
+        _ => ("", tag),
+    }
+}
+
+pub fn namespace(tag: &str) -> &str {
+    split(tag).0
+}
+
+/// Makes a tag out of text from outside pkrs, like a folder name. Tags can't
+/// contain whitespace, so `long hair` becomes `long_hair`.
+pub fn clean(namespace: &str, text: &str) -> Option<String> {
+    let name = text.split_whitespace().collect::<Vec<_>>().join("_");
+    match (name.is_empty(), namespace.is_empty()) {
+        (true, _) => None,
+        (false, true) => Some(name),
+        (false, false) => Some(format!("{}:{}", namespace, name)),
+    }
+}
+
+/// Sorts tags by their namespace's position in the registry, then namespaces
+/// outside the registry, then general tags.
+pub fn sort(tags: &mut [String], namespaces: &[Namespace]) {
+    tags.sort_by_cached_key(|tag| {
+        let (namespace, name) = split(tag);
+        let rank = match namespace {
+            "" => namespaces.len() + 1,
+            namespace => namespaces
+                .iter()
+                .position(|n| n.name == namespace)
+                .unwrap_or(namespaces.len()),
+        };
+        (rank, namespace.to_string(), name.to_string())
+    });
+}
+
+/// A namespace in the registry. Its position in the registry decides the
+/// order it's shown in.
+#[derive(Debug, Clone)]
+pub struct Namespace {
+    pub name: String,
+    /// `0xRRGGBB`
+    pub color: Option<u32>,
+}
+
+pub fn parse_color(color: &str) -> Result<u32, String> {
+    u32::from_str_radix(color.trim_start_matches('#'), 16)
+        .ok()
+        .filter(|c| *c <= 0xFFFFFF)
+        .ok_or_else(|| format!("'{}' is not a #rrggbb colour", color))
+}
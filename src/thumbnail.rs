@@ -1,21 +1,164 @@
+use crate::config::Config;
+use crate::tag;
+use crate::video;
+use image::codecs::{jpeg::JpegEncoder, png::PngEncoder, webp::WebPEncoder};
+use image::imageops::FilterType;
 use image::io::Reader as ImageReader;
-use image::EncodableLayout;
+use image::{DynamicImage, ImageEncoder, Rgb, RgbImage, RgbaImage};
+use serde_derive::{Deserialize, Serialize};
 use std::error::Error;
-use std::fs;
-use std::path::Path;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
 
-pub static THUMBNAIL_SIZE: u32 = 180;
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Jpeg,
+    Png,
+    /// Lossless, so `thumbnail_quality` doesn't apply.
+    WebP,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Jpeg => "jpg",
+            Format::Png => "png",
+            Format::WebP => "webp",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Filter {
+    /// Fast integer sampling, good enough for most thumbnails.
+    #[default]
+    Fast,
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl Filter {
+    fn filter_type(self) -> Option<FilterType> {
+        match self {
+            Filter::Fast => None,
+            Filter::Nearest => Some(FilterType::Nearest),
+            Filter::Triangle => Some(FilterType::Triangle),
+            Filter::CatmullRom => Some(FilterType::CatmullRom),
+            Filter::Gaussian => Some(FilterType::Gaussian),
+            Filter::Lanczos3 => Some(FilterType::Lanczos3),
+        }
+    }
+}
+
+/// What transparent pixels are drawn over, written as `transparent`,
+/// `checkerboard` or a `#rrggbb` colour.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Background {
+    /// Keeps alpha in PNG and WebP. JPEG has no alpha channel, so it falls
+    /// back to the checkerboard.
+    Transparent,
+    #[default]
+    Checkerboard,
+    /// `0xRRGGBB`
+    Color(u32),
+}
+
+impl Background {
+    fn color_at(self, x: u32, y: u32) -> u32 {
+        match self {
+            Background::Color(color) => color,
+            _ if (x / 8 + y / 8).is_multiple_of(2) => 0xFFFFFF,
+            _ => 0xCCCCCC,
+        }
+    }
+}
+
+impl TryFrom<String> for Background {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "transparent" => Ok(Background::Transparent),
+            "checkerboard" => Ok(Background::Checkerboard),
+            color => tag::parse_color(color).map(Background::Color),
+        }
+    }
+}
+
+impl From<Background> for String {
+    fn from(background: Background) -> Self {
+        match background {
+            Background::Transparent => "transparent".to_string(),
+            Background::Checkerboard => "checkerboard".to_string(),
+            Background::Color(color) => format!("#{:06x}", color),
+        }
+    }
+}
+
+/// Writes a thumbnail of `from` for each `(size, path)` in `to`.
+pub fn create(from: &Path, to: &[(u32, PathBuf)], config: &Config) -> Result<(), Box<dyn Error>> {
+    let image = match video::is_video(from) {
+        true => video::extract_frame(from)?,
+        false => ImageReader::open(from)?.decode()?,
+    };
+    for (size, path) in to {
+        write(&image, *size, path, config)?;
+    }
+    Ok(())
+}
+
+fn write(
+    image: &DynamicImage,
+    size: u32,
+    to: &Path,
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let thumbnail = match config.thumbnail_filter.filter_type() {
+        None => image.thumbnail(size, size),
+        Some(filter) => image.resize(size, size, filter),
+    };
 
-pub fn create(from: &Path, to: &Path) -> Result<(), Box<dyn Error>> {
-    let image = ImageReader::open(from)?.decode()?;
-    let thumbnail = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
     fs::create_dir_all(to.parent().unwrap())?;
-    image::save_buffer(
-        to,
-        thumbnail.to_rgb8().as_bytes(),
-        thumbnail.width(),
-        thumbnail.height(),
-        image::ColorType::Rgb8,
-    )?;
+    let file = BufWriter::new(File::create(to)?);
+    let keep_alpha = config.thumbnail_background == Background::Transparent
+        && !matches!(config.thumbnail_format, Format::Jpeg);
+
+    let thumbnail = match thumbnail.color().has_alpha() {
+        false => DynamicImage::ImageRgb8(thumbnail.to_rgb8()),
+        true if keep_alpha => DynamicImage::ImageRgba8(thumbnail.to_rgba8()),
+        true => {
+            DynamicImage::ImageRgb8(flatten(&thumbnail.to_rgba8(), config.thumbnail_background))
+        }
+    };
+    let (bytes, width, height) = (thumbnail.as_bytes(), thumbnail.width(), thumbnail.height());
+    let color = thumbnail.color();
+
+    match config.thumbnail_format {
+        Format::Jpeg => JpegEncoder::new_with_quality(file, config.thumbnail_quality)
+            .write_image(bytes, width, height, color)?,
+        Format::Png => PngEncoder::new(file).write_image(bytes, width, height, color)?,
+        Format::WebP => WebPEncoder::new_lossless(file).write_image(bytes, width, height, color)?,
+    }
     Ok(())
 }
+
+/// Blends `image` over `background`, dropping the alpha channel.
+fn flatten(image: &RgbaImage, background: Background) -> RgbImage {
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        let [_, bg_r, bg_g, bg_b] = background.color_at(x, y).to_be_bytes();
+        let mix = |c: u8, bg: u8| {
+            let (c, bg, a) = (c as u32, bg as u32, a as u32);
+            ((c * a + bg * (255 - a)) / 255) as u8
+        };
+        Rgb([mix(r, bg_r), mix(g, bg_g), mix(b, bg_b)])
+    })
+}
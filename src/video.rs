@@ -0,0 +1,74 @@
+use image::DynamicImage;
+use serde_json::Value;
+use std::error::Error;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Output};
+
+pub static EXTENSIONS: [&str; 7] = ["mp4", "m4v", "webm", "mkv", "mov", "avi", "wmv"];
+
+#[derive(Debug, Default)]
+pub struct Info {
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    /// Seconds
+    pub duration: Option<f64>,
+    pub codec: Option<String>,
+}
+
+pub fn is_video(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Reads the duration and first video stream of `path` with ffprobe.
+pub fn probe(path: &Path) -> Result<Info, Box<dyn Error>> {
+    let output = run(Command::new("ffprobe")
+        .args(["-v", "error", "-of", "json", "-select_streams", "v:0"])
+        .args([
+            "-show_entries",
+            "format=duration:stream=codec_name,width,height",
+        ])
+        .arg(path))?;
+
+    let json: Value = serde_json::from_slice(&output.stdout)?;
+    let stream = &json["streams"][0];
+
+    Ok(Info {
+        width: stream["width"].as_i64(),
+        height: stream["height"].as_i64(),
+        // ffprobe writes the duration as a string.
+        duration: json["format"]["duration"]
+            .as_str()
+            .and_then(|d| d.parse().ok()),
+        codec: stream["codec_name"].as_str().map(String::from),
+    })
+}
+
+/// Grabs a frame a tenth of the way in, so it's less likely to be a black
+/// intro frame.
+pub fn extract_frame(path: &Path) -> Result<DynamicImage, Box<dyn Error>> {
+    let seek = probe(path)?.duration.unwrap_or_default() * 0.1;
+
+    let output = run(Command::new("ffmpeg")
+        .args(["-v", "error", "-ss", &format!("{:.3}", seek), "-i"])
+        .arg(path)
+        .args(["-frames:v", "1", "-f", "image2pipe", "-vcodec", "png", "-"]))?;
+
+    Ok(image::load_from_memory(&output.stdout)?)
+}
+
+fn run(command: &mut Command) -> Result<Output, Box<dyn Error>> {
+    let program = command.get_program().to_string_lossy().to_string();
+    let output = command.output().map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => format!("{} not found, install ffmpeg for videos", program),
+        _ => format!("Failed to run {}: {}", program, e),
+    })?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("{} failed: {}", program, stderr.trim()).into());
+    }
+    Ok(output)
+}
@@ -0,0 +1,372 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufReader},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use eframe::egui::{self, Key};
+use image::{
+    codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder},
+    AnimationDecoder, Frame, ImageFormat, RgbaImage,
+};
+use poll_promise::Promise;
+
+use crate::{config::Config, post::Post};
+
+/// Browsers play frames with a delay this short or unset at `DEFAULT_DELAY`.
+const UNSET_DELAY: Duration = Duration::from_millis(10);
+const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+const MIN_ZOOM: f32 = 0.05;
+const MAX_ZOOM: f32 = 32.0;
+
+/// What the viewer wants the app to do after a frame.
+pub enum Action {
+    Close,
+    Previous,
+    Next,
+    OpenExternally,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Zoom {
+    /// Shrinks large images to fit, small ones are shown at 1:1.
+    Fit,
+    Scale(f32),
+}
+
+struct ViewerFrame {
+    texture: egui::TextureHandle,
+    delay: Duration,
+}
+
+struct Playback {
+    current: usize,
+    paused: bool,
+    frame_started: Instant,
+}
+
+pub struct Viewer {
+    /// Position of the post in the current results.
+    pub index: usize,
+    title: String,
+    frames: Promise<Result<Vec<ViewerFrame>, String>>,
+    playback: Playback,
+    zoom: Zoom,
+    fit_scale: f32,
+}
+
+/// Whether `post` is an image the viewer can decode.
+pub fn can_view(post: &Post) -> bool {
+    post.extension
+        .as_ref()
+        .is_some_and(|ext| ImageFormat::from_extension(ext).is_some())
+}
+
+impl Viewer {
+    pub fn open(ctx: &egui::Context, index: usize, post: &Post, config: &Config) -> Self {
+        let path = post.get_db_file(config);
+        let ctx = ctx.clone();
+        let frames = match can_view(post) {
+            false => Promise::from_ready(Err(format!("Can't show {} here", post.original_name))),
+            true => Promise::spawn_thread("load_frames", move || {
+                let frames = decode_frames(&path).map_err(|e| e.to_string())?;
+                Ok(frames
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, (image, delay))| {
+                        let size = [image.width() as _, image.height() as _];
+                        let image = egui::ColorImage::from_rgba_unmultiplied(
+                            size,
+                            image.as_flat_samples().as_slice(),
+                        );
+                        ViewerFrame {
+                            texture: ctx.load_texture(
+                                format!("frame{}", i),
+                                image,
+                                Default::default(),
+                            ),
+                            delay,
+                        }
+                    })
+                    .collect())
+            }),
+        };
+
+        Viewer {
+            index,
+            title: format!("#{} {}", post.id, post.original_name),
+            frames,
+            playback: Playback {
+                current: 0,
+                paused: false,
+                frame_started: Instant::now(),
+            },
+            zoom: Zoom::Fit,
+            fit_scale: 1.0,
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) -> Option<Action> {
+        let mut action = None;
+
+        if ui.memory(|m| m.focus().is_none()) {
+            ui.input(|i| {
+                if i.key_pressed(Key::Escape) {
+                    action = Some(Action::Close);
+                }
+                if !i.modifiers.shift && i.key_pressed(Key::ArrowLeft) {
+                    action = Some(Action::Previous);
+                }
+                if !i.modifiers.shift && i.key_pressed(Key::ArrowRight) {
+                    action = Some(Action::Next);
+                }
+                if i.key_pressed(Key::F) {
+                    self.zoom = Zoom::Fit;
+                }
+                if i.key_pressed(Key::Num1) {
+                    self.zoom = Zoom::Scale(1.0);
+                }
+                if i.key_pressed(Key::PlusEquals) {
+                    self.zoom_by(1.25);
+                }
+                if i.key_pressed(Key::Minus) {
+                    self.zoom_by(0.8);
+                }
+            });
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("⬅").on_hover_text("Previous (Left)").clicked() {
+                action = Some(Action::Previous);
+            }
+            if ui.button("➡").on_hover_text("Next (Right)").clicked() {
+                action = Some(Action::Next);
+            }
+            ui.separator();
+
+            if ui
+                .selectable_label(self.zoom == Zoom::Fit, "Fit")
+                .on_hover_text("F")
+                .clicked()
+            {
+                self.zoom = Zoom::Fit;
+            }
+            if ui
+                .selectable_label(self.zoom == Zoom::Scale(1.0), "1:1")
+                .on_hover_text("1")
+                .clicked()
+            {
+                self.zoom = Zoom::Scale(1.0);
+            }
+            if ui.button("-").on_hover_text("Zoom out (-)").clicked() {
+                self.zoom_by(0.8);
+            }
+            if ui.button("+").on_hover_text("Zoom in (+)").clicked() {
+                self.zoom_by(1.25);
+            }
+            ui.label(format!("{:.0}%", self.scale() * 100.0));
+            ui.separator();
+
+            if ui.button("Open Externally").clicked() {
+                action = Some(Action::OpenExternally);
+            }
+            if ui.button("✖").on_hover_text("Close (Esc)").clicked() {
+                action = Some(Action::Close);
+            }
+            ui.label(&self.title);
+        });
+        ui.separator();
+
+        match self.frames.ready() {
+            None => {
+                ui.centered_and_justified(|ui| ui.spinner());
+            }
+            Some(Err(e)) => {
+                ui.colored_label(ui.visuals().error_fg_color, e);
+            }
+            Some(Ok(frames)) => {
+                if frames.len() > 1 {
+                    controls(ui, frames, &mut self.playback);
+                }
+
+                let texture = &frames[self.playback.current].texture;
+                let size = texture.size_vec2();
+                let available = ui.available_size();
+                self.fit_scale = (available.x / size.x).min(available.y / size.y).min(1.0);
+
+                // Ctrl+scroll or pinch over the image zooms.
+                let zoom_delta = ui.input(|i| i.zoom_delta());
+                if zoom_delta != 1.0 && ui.rect_contains_pointer(ui.available_rect_before_wrap()) {
+                    self.zoom = self.zoom.by(zoom_delta, self.fit_scale);
+                }
+
+                match self.zoom {
+                    Zoom::Fit => {
+                        ui.centered_and_justified(|ui| ui.image(texture, size * self.fit_scale));
+                    }
+                    Zoom::Scale(scale) => {
+                        egui::ScrollArea::both()
+                            .auto_shrink([false, false])
+                            .drag_to_scroll(true)
+                            .show(ui, |ui| ui.image(texture, size * scale));
+                    }
+                }
+            }
+        }
+
+        action
+    }
+
+    fn scale(&self) -> f32 {
+        self.zoom.scale(self.fit_scale)
+    }
+
+    fn zoom_by(&mut self, factor: f32) {
+        self.zoom = self.zoom.by(factor, self.fit_scale);
+    }
+}
+
+impl Zoom {
+    fn scale(self, fit_scale: f32) -> f32 {
+        match self {
+            Zoom::Fit => fit_scale,
+            Zoom::Scale(scale) => scale,
+        }
+    }
+
+    fn by(self, factor: f32, fit_scale: f32) -> Zoom {
+        Zoom::Scale((self.scale(fit_scale) * factor).clamp(MIN_ZOOM, MAX_ZOOM))
+    }
+}
+
+fn controls(ui: &mut egui::Ui, frames: &[ViewerFrame], playback: &mut Playback) {
+    let step = |playback: &mut Playback, forward: bool| {
+        playback.paused = true;
+        playback.current = match forward {
+            true => (playback.current + 1) % frames.len(),
+            false => (playback.current + frames.len() - 1) % frames.len(),
+        };
+    };
+
+    if ui.memory(|m| m.focus().is_none()) {
+        ui.input(|i| {
+            if i.key_pressed(Key::Space) {
+                playback.paused = !playback.paused;
+                playback.frame_started = Instant::now();
+            }
+            if i.modifiers.shift && i.key_pressed(Key::ArrowLeft) {
+                step(playback, false);
+            }
+            if i.modifiers.shift && i.key_pressed(Key::ArrowRight) {
+                step(playback, true);
+            }
+        });
+    }
+
+    ui.horizontal(|ui| {
+        if ui
+            .button("⏮")
+            .on_hover_text("Previous frame (Shift+Left)")
+            .clicked()
+        {
+            step(playback, false);
+        }
+        let (icon, hint) = match playback.paused {
+            true => ("▶", "Play (Space)"),
+            false => ("⏸", "Pause (Space)"),
+        };
+        if ui.button(icon).on_hover_text(hint).clicked() {
+            playback.paused = !playback.paused;
+            playback.frame_started = Instant::now();
+        }
+        if ui
+            .button("⏭")
+            .on_hover_text("Next frame (Shift+Right)")
+            .clicked()
+        {
+            step(playback, true);
+        }
+
+        let frame = &frames[playback.current];
+        ui.label(format!(
+            "{}/{}  {}ms",
+            playback.current + 1,
+            frames.len(),
+            frame.delay.as_millis()
+        ));
+    });
+
+    if playback.paused {
+        return;
+    }
+
+    let delay = frames[playback.current].delay;
+    if playback.frame_started.elapsed() >= delay {
+        playback.current = (playback.current + 1) % frames.len();
+        playback.frame_started += delay;
+
+        // Start over rather than racing to catch up after a stall.
+        if playback.frame_started.elapsed() >= frames[playback.current].delay {
+            playback.frame_started = Instant::now();
+        }
+    }
+
+    let remaining = frames[playback.current]
+        .delay
+        .saturating_sub(playback.frame_started.elapsed());
+    ui.ctx().request_repaint_after(remaining);
+}
+
+/// Decodes every frame of an animated GIF, APNG or WebP, or the image itself
+/// as a single frame otherwise.
+fn decode_frames(path: &Path) -> Result<Vec<(RgbaImage, Duration)>, Box<dyn Error>> {
+    let reader = || -> io::Result<BufReader<File>> { Ok(BufReader::new(File::open(path)?)) };
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    let frames = match extension.as_str() {
+        "gif" => GifDecoder::new(reader()?)?.into_frames().collect_frames()?,
+        "png" => {
+            let decoder = PngDecoder::new(reader()?)?;
+            match decoder.is_apng() {
+                true => decoder.apng().into_frames().collect_frames()?,
+                false => vec![],
+            }
+        }
+        "webp" => {
+            let decoder = WebPDecoder::new(reader()?)?;
+            match decoder.has_animation() {
+                true => decoder.into_frames().collect_frames()?,
+                false => vec![],
+            }
+        }
+        _ => vec![],
+    };
+
+    if frames.is_empty() {
+        return Ok(vec![(image::open(path)?.to_rgba8(), DEFAULT_DELAY)]);
+    }
+
+    Ok(frames
+        .into_iter()
+        .map(|frame| {
+            let delay = frame_delay(&frame);
+            (frame.into_buffer(), delay)
+        })
+        .collect())
+}
+
+fn frame_delay(frame: &Frame) -> Duration {
+    let (numer, denom) = frame.delay().numer_denom_ms();
+    let delay = Duration::from_secs_f64(numer as f64 / denom.max(1) as f64 / 1000.0);
+    match delay <= UNSET_DELAY {
+        true => DEFAULT_DELAY,
+        false => delay,
+    }
+}
@@ -1,31 +1,58 @@
 use std::{
     error::Error,
     path::PathBuf,
-    sync::mpsc::{Receiver, Sender},
+    sync::mpsc::{Receiver, Sender, TryRecvError},
+    thread,
 };
 
 use eframe::egui::Context;
 
 use crate::{
-    db::Database,
+    db::{Database, Page},
     gui::PostThumbnail,
+    import, maintenance,
     message::{FromGUI, FromWorker},
     post::Post,
-    search,
+    search::{self, Query},
+    sidecar,
 };
 
+/// How many posts are sent to the GUI at a time.
+const PAGE_SIZE: usize = 200;
+
+/// How many tags are suggested while typing.
+const COMPLETIONS: usize = 10;
+
+/// How many posts to read the metadata of between requests.
+const METADATA_CHUNK: usize = 50;
+
 pub struct Worker {
     tx: Sender<FromWorker>,
     db: Database,
     ctx: Option<Context>,
+    query: Option<Query>,
+    loaded: usize,
 }
 
 impl Worker {
     pub fn create(tx: Sender<FromWorker>, rx: Receiver<FromGUI>, db: Database) {
-        let mut worker = Worker { tx, db, ctx: None };
+        let mut worker = Worker {
+            tx,
+            db,
+            ctx: None,
+            query: None,
+            loaded: 0,
+        };
 
         worker.tx.send(FromWorker::RequestContext).unwrap();
-        worker.run(rx).unwrap();
+        let namespaces = worker.db.get_namespaces().unwrap();
+        worker
+            .tx
+            .send(FromWorker::SetNamespaces(namespaces))
+            .unwrap();
+        if let Err(e) = worker.run(rx) {
+            eprintln!("Worker stopped. {}", e);
+        }
     }
 
     fn send(&self, msg: FromWorker) -> Result<(), Box<dyn Error>> {
@@ -37,110 +64,242 @@ impl Worker {
     }
 
     pub fn run(&mut self, rx: Receiver<FromGUI>) -> Result<(), Box<dyn Error>> {
-        for received in rx {
-            match received {
-                FromGUI::SendContext(ctx) => self.ctx = Some(ctx),
-
-                FromGUI::RequestAllPosts => {
-                    let mut posts: Vec<PostThumbnail> = self
-                        .db
-                        .all()?
-                        .into_iter()
-                        .map(PostThumbnail::from)
-                        .collect();
-
-                    posts.reverse();
-                    self.send(FromWorker::SetPosts(posts))?;
-                }
+        // Posts imported by older versions are read a chunk at a time while
+        // there are no requests waiting, so the GUI isn't kept waiting.
+        let mut missing = self.db.get_posts_without_metadata()?;
+        let total = missing.len();
 
-                FromGUI::RequestDroppedNewPosts(dropped) => {
-                    let paths = dropped.into_iter().filter_map(|p| p.path).collect();
-                    self.create_posts(paths)?;
-                }
+        loop {
+            let received = match missing.is_empty() {
+                true => match rx.recv() {
+                    Ok(received) => received,
+                    Err(_) => break,
+                },
+                false => match rx.try_recv() {
+                    Ok(received) => received,
+                    Err(TryRecvError::Empty) => {
+                        if let Err(e) = self.backfill_metadata(&mut missing, total) {
+                            missing.clear();
+                            self.report(e)?;
+                        }
+                        continue;
+                    }
+                    Err(TryRecvError::Disconnected) => break,
+                },
+            };
 
-                FromGUI::RequestPickedNewPosts(picked) => {
-                    self.create_posts(picked)?;
-                }
+            // A failed request shouldn't take the worker down with it.
+            if let Err(e) = self.handle(received) {
+                self.report(e)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn report(&mut self, e: Box<dyn Error>) -> Result<(), Box<dyn Error>> {
+        eprintln!("{}", e);
+        if !self.db.conn.is_autocommit() {
+            self.db.rollback()?;
+        }
+        self.send(FromWorker::ShowProgress(false))?;
+        self.send(FromWorker::ShowError(e.to_string()))
+    }
+
+    fn handle(&mut self, received: FromGUI) -> Result<(), Box<dyn Error>> {
+        match received {
+            FromGUI::SendContext(ctx) => self.ctx = Some(ctx),
+
+            FromGUI::RequestAllPosts => self.set_query(Query::default())?,
+
+            FromGUI::RequestMorePosts => {
+                let posts = self.next_page()?;
+                self.send(FromWorker::AppendPosts(posts))?;
+            }
+
+            FromGUI::RequestDroppedNewPosts(dropped, options) => {
+                let paths = dropped.into_iter().filter_map(|p| p.path).collect();
+                self.create_posts(paths, options)?;
+            }
+
+            FromGUI::RequestPickedNewPosts(picked, options) => {
+                self.create_posts(picked, options)?;
+            }
 
-                FromGUI::SetSelected(selected) => {
-                    self.send(FromWorker::SetSelected(selected))?;
+            FromGUI::SetSelected(selected) => {
+                self.send(FromWorker::SetSelected(selected))?;
+            }
+            FromGUI::RemoveTag(post_id, tag) => {
+                let mut post = self.db.get_post_id(post_id)?;
+                let warnings = post.remove_tag(&tag, &self.db)?;
+                self.send(FromWorker::SetPostTags(post.id, post.tags))?;
+                if !warnings.is_empty() {
+                    self.send(FromWorker::ShowWarnings(warnings))?;
                 }
-                FromGUI::RemoveTag(post_id, tag) => {
-                    let tag_id = self.db.get_tag_id(&tag)?;
-                    self.db.remove_tagging(post_id, tag_id)?;
+            }
+            FromGUI::AddTag(post_id, tag) => {
+                let mut post = self.db.get_post_id(post_id)?;
+                post.add_tag(&tag, &self.db)?;
+                self.send(FromWorker::SetPostTags(post.id, post.tags))?;
+            }
+            FromGUI::RenameTag(old, new) => {
+                if let Ok(tag_id) = self.db.get_tag_id(&old) {
+                    let new = self.db.canonical_tag(&new)?;
+                    self.db.begin()?;
+                    let changed = self.db.rename_tag(tag_id, &new)?;
+                    self.db.commit()?;
+
+                    println!("'{}' -> '{}'. Changed {} posts", old, new, changed);
+                    self.send(FromWorker::RenamedTag(old, new))?;
                 }
-                FromGUI::AddTag(post_id, tag) => {
-                    let tag_id = self.db.get_or_create_tag(&tag)?;
-                    self.db.insert_tagging(post_id, tag_id)?;
+            }
+            FromGUI::Search(query) => match search::parse(&query) {
+                Ok(query) => {
+                    self.send(FromWorker::SetSearchError(None))?;
+                    self.set_query(query)?;
                 }
-                FromGUI::Search(query) => {
-                    let search: Vec<String> = query.split(' ').map(|s| s.to_owned()).collect();
-                    let posts = search::new(search, &mut self.db)?
-                        .into_iter()
-                        .map(PostThumbnail::from)
-                        .collect();
-                    self.send(FromWorker::SetPosts(posts))?;
+                Err(e) => self.send(FromWorker::SetSearchError(Some(e.to_string())))?,
+            },
+            FromGUI::CompleteTag(prefix) => {
+                let tags = self.db.complete_tags(&prefix, COMPLETIONS)?;
+                self.send(FromWorker::SetCompletions(prefix, tags))?;
+            }
+            FromGUI::FindDuplicates(threshold) => self.find_duplicates(threshold)?,
+            FromGUI::MergeDuplicates(keep, drop) => {
+                let mut keep = self.db.get_post_id(keep)?;
+                for post_id in &drop {
+                    let post = self.db.get_post_id(*post_id)?;
+                    // The file to keep was picked in the comparison.
+                    keep.merge(post, false, &self.db)?;
+                    println!("Merged post #{} into #{}", post_id, keep.id);
                 }
+
+                // The removed posts no longer count towards the next page.
+                self.loaded = self.loaded.saturating_sub(drop.len());
+                self.send(FromWorker::SetPostTags(keep.id, keep.tags))?;
+                self.send(FromWorker::RemovedPosts(drop))?;
             }
         }
         Ok(())
     }
 
-    fn create_posts(&mut self, paths: Vec<PathBuf>) -> Result<(), Box<dyn Error>> {
+    fn set_query(&mut self, query: Query) -> Result<(), Box<dyn Error>> {
+        println!("Searching for: {}", query);
+        self.query = Some(query);
+        self.loaded = 0;
+
+        let posts = self.next_page()?;
+        self.send(FromWorker::SetPosts(posts))
+    }
+
+    fn next_page(&mut self) -> Result<Vec<PostThumbnail>, Box<dyn Error>> {
+        let query = match &self.query {
+            Some(query) => query,
+            None => return Ok(Vec::new()),
+        };
+
+        let posts = query.run(&Page::new(self.loaded, PAGE_SIZE), &self.db)?;
+        self.loaded += posts.len();
+        Ok(posts.into_iter().map(PostThumbnail::from).collect())
+    }
+
+    /// Reads the metadata of the next chunk of `missing`, out of `total`.
+    fn backfill_metadata(
+        &mut self,
+        missing: &mut Vec<Post>,
+        total: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let chunk: Vec<_> = missing.drain(..missing.len().min(METADATA_CHUNK)).collect();
+        let jobs = thread::available_parallelism().map_or(1, |n| n.get());
+        maintenance::backfill_metadata(&chunk, jobs, &self.db, |_| {})?;
+
+        let done = total - missing.len();
+        self.send(FromWorker::SetProgress(done as f32, total as f32))?;
+        self.send(FromWorker::SetProgressMessage(Some(format!(
+            "Reading metadata {}/{}",
+            done, total
+        ))))?;
+        self.send(FromWorker::ShowProgress(!missing.is_empty()))
+    }
+
+    fn find_duplicates(&mut self, threshold: u32) -> Result<(), Box<dyn Error>> {
+        self.send(FromWorker::ShowProgress(true))?;
+        self.send(FromWorker::SetProgress(0.0, 1.0))?;
+        self.send(FromWorker::SetProgressMessage(Some(
+            "Finding duplicates...".to_string(),
+        )))?;
+
+        let jobs = thread::available_parallelism().map_or(1, |n| n.get());
+        maintenance::fill_phashes(&self.db, jobs)?;
+        let groups = maintenance::find_duplicates(&self.db, threshold)?;
+        let groups = groups
+            .into_iter()
+            .map(|group| group.into_iter().map(PostThumbnail::from).collect())
+            .collect();
+
+        self.send(FromWorker::SetDuplicates(groups))?;
+        self.send(FromWorker::ShowProgress(false))?;
+        Ok(())
+    }
+
+    fn create_posts(
+        &mut self,
+        paths: Vec<PathBuf>,
+        options: import::Options,
+    ) -> Result<(), Box<dyn Error>> {
         self.send(FromWorker::ShowProgress(true))?;
         self.send(FromWorker::SetProgress(0.0, 100.0))?;
         self.send(FromWorker::SetProgressMessage(Some(
             "Reading...".to_string(),
         )))?;
 
-        let paths = get_paths_recursive(paths);
-        let mut current = 0.0;
-        let total = paths.len() as f32;
-        let mut new_posts = Vec::new();
+        let files = sidecar::remove_sidecars(import::files(paths), &self.db.config);
 
-        for path in paths {
-            let status = format!("{}/{}  {}", current, total, path.display());
-            self.send(FromWorker::SetProgressMessage(Some(status)))?;
+        let (new_posts, errors) = self.import_files(files, &options)?;
 
-            match Post::new(&path, &mut self.db) {
-                Ok(post) => {
-                    new_posts.push(PostThumbnail::from(post));
-                }
-                Err(e) => eprintln!("Failed to add post. {}", e),
-            }
-            current += 1.0;
-            self.send(FromWorker::SetProgress(current, total))?;
-        }
-        new_posts.reverse();
+        self.query = None;
         self.send(FromWorker::SetPosts(new_posts))?;
         self.send(FromWorker::ShowProgress(false))?;
+        if !errors.is_empty() {
+            self.send(FromWorker::ShowError(errors.join("\n")))?;
+        }
 
         Ok(())
     }
-}
 
-fn get_paths_recursive(paths: Vec<PathBuf>) -> Vec<PathBuf> {
-    let mut files = Vec::new();
-    let mut entries = Vec::new();
-
-    for path in paths {
-        match path.is_dir() {
-            true => entries.extend(
-                walkdir::WalkDir::new(path)
-                    .into_iter()
-                    .filter_map(|e| e.ok()),
-            ),
-            false => files.push(path),
-        }
-    }
+    /// Adds and tags each file in its own transaction, so a file that fails
+    /// is skipped instead of stopping the rest. Returns the new posts and why
+    /// the others failed.
+    fn import_files(
+        &mut self,
+        files: Vec<import::File>,
+        options: &import::Options,
+    ) -> Result<(Vec<PostThumbnail>, Vec<String>), Box<dyn Error>> {
+        let mut current = 0.0;
+        let total = files.len() as f32;
+        let mut new_posts = Vec::new();
+        let mut errors = Vec::new();
 
-    files.reserve_exact(entries.len());
+        for file in files {
+            let status = format!("{}/{}  {}", current, total, file.path.display());
+            self.send(FromWorker::SetProgressMessage(Some(status)))?;
+
+            self.db.begin()?;
+            match import::post(&file, options, &mut self.db) {
+                Ok((post, _)) => {
+                    self.db.commit()?;
+                    new_posts.push(PostThumbnail::from(post));
+                }
+                Err(e) => {
+                    self.db.rollback()?;
+                    let error = format!("Failed to add {}. {}", file.path.display(), e);
+                    eprintln!("{}", error);
+                    errors.push(error);
+                }
+            }
 
-    for entry in entries {
-        let path = entry.into_path();
-        if path.is_file() {
-            files.push(path);
+            current += 1.0;
+            self.send(FromWorker::SetProgress(current, total))?;
         }
+        Ok((new_posts, errors))
     }
-    files
 }